@@ -0,0 +1,476 @@
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use log::debug;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::UnixStream;
+use tonic::transport::{Channel, Endpoint, Uri};
+use tower::service_fn;
+
+const UNIX_SCHEME: &str = "unix://";
+const TCP_SCHEME: &str = "tcp://";
+const DEFAULT_RUNTIME_ENDPOINT: &str = "unix:///run/containerd/containerd.sock";
+const DEFAULT_EVICTION_HARD: &str = "memory.available<100Mi,nodefs.available<10%";
+const DEFAULT_CLUSTER_DOMAIN: &str = "cluster.local";
+const DEFAULT_RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Flags understood by KrustletCRI and the environment variables they map to.
+const FLAGS: &[(&str, &str)] = &[
+    ("--container-runtime-endpoint", "CONTAINER_RUNTIME_ENDPOINT"),
+    ("--image-service-endpoint", "IMAGE_SERVICE_ENDPOINT"),
+    ("--kube-reserved", "KUBE_RESERVED"),
+    ("--system-reserved", "SYSTEM_RESERVED"),
+    ("--eviction-hard", "EVICTION_HARD"),
+    ("--runtime-request-timeout", "RUNTIME_REQUEST_TIMEOUT"),
+    ("--image-pull-timeout", "IMAGE_PULL_TIMEOUT"),
+    ("--status-request-timeout", "STATUS_REQUEST_TIMEOUT"),
+    ("--cluster-dns", "CLUSTER_DNS"),
+    ("--cluster-domain", "CLUSTER_DOMAIN"),
+    ("--resolv-conf", "RESOLV_CONF"),
+    ("--allowed-unsafe-sysctls", "ALLOWED_UNSAFE_SYSCTLS"),
+    ("--cgroup-driver", "CGROUP_DRIVER"),
+    ("--cpu-cfs-quota-period", "CPU_CFS_QUOTA_PERIOD"),
+    ("--seccomp-profile-root", "SECCOMP_PROFILE_ROOT"),
+];
+
+/// Boolean flags, which only take a value in the `--flag=value` form.
+const BOOL_FLAGS: &[(&str, &str)] = &[("--cpu-cfs-quota", "CPU_CFS_QUOTA")];
+
+/// Address of a CRI gRPC service.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CriEndpoint {
+    /// `unix:///path/to/socket`
+    Unix(PathBuf),
+    /// `tcp://host:port`
+    Tcp(String),
+}
+
+impl CriEndpoint {
+    pub async fn connect(&self) -> anyhow::Result<Channel> {
+        debug!("Connecting to {}.", self);
+        let channel = match self {
+            CriEndpoint::Unix(path) => {
+                let path = path.clone();
+                // The URI is ignored by the connector, but tonic requires a valid one.
+                Endpoint::try_from("lttp://[::]:50051")?
+                    .connect_with_connector(service_fn(move |_: Uri| {
+                        UnixStream::connect(path.clone())
+                    }))
+                    .await?
+            }
+            CriEndpoint::Tcp(address) => {
+                Endpoint::from_shared(format!("http://{}", address))?
+                    .connect()
+                    .await?
+            }
+        };
+        Ok(channel)
+    }
+}
+
+impl std::str::FromStr for CriEndpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if let Some(path) = s.strip_prefix(UNIX_SCHEME) {
+            Ok(CriEndpoint::Unix(PathBuf::from(path)))
+        } else if let Some(address) = s.strip_prefix(TCP_SCHEME) {
+            Ok(CriEndpoint::Tcp(address.to_string()))
+        } else if s.starts_with('/') {
+            // Bare socket paths are accepted for compatibility with older configs.
+            Ok(CriEndpoint::Unix(PathBuf::from(s)))
+        } else {
+            anyhow::bail!(
+                "Unsupported CRI endpoint {}, expected unix:// or tcp:// URI.",
+                s
+            )
+        }
+    }
+}
+
+impl std::fmt::Display for CriEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CriEndpoint::Unix(path) => write!(f, "{}{}", UNIX_SCHEME, path.display()),
+            CriEndpoint::Tcp(address) => write!(f, "{}{}", TCP_SCHEME, address),
+        }
+    }
+}
+
+/// Amount of a resource at which pods are evicted, such as `memory.available<100Mi`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Threshold {
+    Quantity(Quantity),
+    /// Percentage of capacity.
+    Percentage(f64),
+}
+
+/// How the runtime manages cgroups, which decides the format of cgroup parents.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CgroupDriver {
+    Cgroupfs,
+    Systemd,
+}
+
+impl std::str::FromStr for CgroupDriver {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "cgroupfs" => Ok(CgroupDriver::Cgroupfs),
+            "systemd" => Ok(CgroupDriver::Systemd),
+            _ => anyhow::bail!(
+                "Unsupported cgroup driver {}, expected cgroupfs or systemd.",
+                s
+            ),
+        }
+    }
+}
+
+/// Deadlines applied to CRI calls.
+#[derive(Clone, Copy, Debug)]
+pub struct Timeouts {
+    /// Calls which create, start, stop or remove sandboxes and containers.
+    pub runtime: Duration,
+    /// `PullImage`, which may download large images.
+    pub image_pull: Duration,
+    /// Read-only version, status and list calls.
+    pub status: Duration,
+}
+
+impl Timeouts {
+    pub fn new_from_env() -> anyhow::Result<Self> {
+        Ok(Timeouts {
+            runtime: duration_from_env("RUNTIME_REQUEST_TIMEOUT", "2m")?,
+            image_pull: duration_from_env("IMAGE_PULL_TIMEOUT", "10m")?,
+            status: duration_from_env("STATUS_REQUEST_TIMEOUT", "10s")?,
+        })
+    }
+}
+
+/// KrustletCRI specific configuration.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub runtime_endpoint: CriEndpoint,
+    pub image_endpoint: CriEndpoint,
+    /// Resources reserved for Kubernetes system daemons.
+    pub kube_reserved: BTreeMap<String, Quantity>,
+    /// Resources reserved for operating system daemons.
+    pub system_reserved: BTreeMap<String, Quantity>,
+    /// Hard eviction thresholds, keyed by eviction signal.
+    pub eviction_hard: BTreeMap<String, Threshold>,
+    pub timeouts: Timeouts,
+    /// IP addresses of the cluster DNS service, used by `ClusterFirst` pods.
+    pub cluster_dns: Vec<String>,
+    /// Domain appended to the search paths of `ClusterFirst` pods.
+    pub cluster_domain: String,
+    /// Resolver configuration used by `Default` pods, empty for none.
+    pub resolv_conf: String,
+    /// Sysctls pods may set beyond the safe set, either names or prefixes ending in `*`.
+    pub allowed_unsafe_sysctls: Vec<String>,
+    /// Must match the cgroup driver configured in the runtime.
+    pub cgroup_driver: CgroupDriver,
+    /// Whether CPU limits are enforced with CFS quotas.
+    pub cpu_cfs_quota: bool,
+    /// CFS period of containers with a CPU limit.
+    pub cpu_cfs_quota_period: Duration,
+    /// Directory `localhost/` seccomp profiles are resolved against, defaulting to `seccomp`
+    /// under the data directory.
+    pub seccomp_profile_root: Option<PathBuf>,
+}
+
+impl Config {
+    /// Loads configuration from KrustletCRI flags, falling back to environment variables.
+    ///
+    /// `kubelet::config::Config` rejects flags it does not know about, so if any KrustletCRI
+    /// flags are present they are moved into their environment variables and the process is
+    /// re-executed without them.
+    pub fn new_from_flags() -> anyhow::Result<Self> {
+        let mut args: Vec<OsString> = std::env::args_os().collect();
+        let mut vars = vec![];
+        for (flag, var) in FLAGS {
+            while let Some(value) = take_flag(&mut args, flag)? {
+                vars.push((*var, value));
+            }
+        }
+        for (flag, var) in BOOL_FLAGS {
+            while let Some(value) = take_bool_flag(&mut args, flag) {
+                vars.push((*var, value));
+            }
+        }
+        if !vars.is_empty() {
+            debug!("Re-executing with {:?}.", &vars);
+            let error = std::process::Command::new(std::env::current_exe()?)
+                .args(&args[1..])
+                .envs(vars)
+                .exec();
+            anyhow::bail!(error);
+        }
+        Config::new_from_env()
+    }
+
+    pub fn new_from_env() -> anyhow::Result<Self> {
+        let runtime_endpoint: CriEndpoint = std::env::var("CONTAINER_RUNTIME_ENDPOINT")
+            .unwrap_or_else(|_| DEFAULT_RUNTIME_ENDPOINT.to_string())
+            .parse()?;
+        let image_endpoint = match std::env::var("IMAGE_SERVICE_ENDPOINT") {
+            Ok(endpoint) => endpoint.parse()?,
+            Err(_) => runtime_endpoint.clone(),
+        };
+        Ok(Config {
+            runtime_endpoint,
+            image_endpoint,
+            kube_reserved: parse_resource_list(
+                &std::env::var("KUBE_RESERVED").unwrap_or_default(),
+            )?,
+            system_reserved: parse_resource_list(
+                &std::env::var("SYSTEM_RESERVED").unwrap_or_default(),
+            )?,
+            eviction_hard: parse_thresholds(
+                &std::env::var("EVICTION_HARD")
+                    .unwrap_or_else(|_| DEFAULT_EVICTION_HARD.to_string()),
+            )?,
+            timeouts: Timeouts::new_from_env()?,
+            cluster_dns: parse_ips(&std::env::var("CLUSTER_DNS").unwrap_or_default())?,
+            cluster_domain: std::env::var("CLUSTER_DOMAIN")
+                .unwrap_or_else(|_| DEFAULT_CLUSTER_DOMAIN.to_string()),
+            resolv_conf: std::env::var("RESOLV_CONF")
+                .unwrap_or_else(|_| DEFAULT_RESOLV_CONF.to_string()),
+            allowed_unsafe_sysctls: std::env::var("ALLOWED_UNSAFE_SYSCTLS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|sysctl| !sysctl.is_empty())
+                .map(String::from)
+                .collect(),
+            cgroup_driver: std::env::var("CGROUP_DRIVER")
+                .unwrap_or_else(|_| "cgroupfs".to_string())
+                .parse()?,
+            cpu_cfs_quota: std::env::var("CPU_CFS_QUOTA")
+                .unwrap_or_else(|_| "true".to_string())
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid CPU_CFS_QUOTA, expected true or false."))?,
+            cpu_cfs_quota_period: duration_from_env("CPU_CFS_QUOTA_PERIOD", "100ms")?,
+            seccomp_profile_root: std::env::var_os("SECCOMP_PROFILE_ROOT").map(PathBuf::from),
+        })
+    }
+}
+
+fn duration_from_env(var: &str, default: &str) -> anyhow::Result<Duration> {
+    let duration = std::env::var(var).unwrap_or_else(|_| default.to_string());
+    parse_duration(&duration)
+        .ok_or_else(|| anyhow::anyhow!("Invalid duration {:?} for {}.", duration, var))
+}
+
+/// Parses a duration such as `90s`, `2m` or `1h30m`.
+fn parse_duration(duration: &str) -> Option<Duration> {
+    let mut total = Duration::from_secs(0);
+    let mut rest = duration.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let value: u64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        total += match &rest[..unit_len] {
+            "ms" => Duration::from_millis(value),
+            "s" => Duration::from_secs(value),
+            "m" => Duration::from_secs(value * 60),
+            "h" => Duration::from_secs(value * 60 * 60),
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+    }
+    Some(total)
+}
+
+/// Parses a list such as `cpu=100m,memory=256Mi`.
+fn parse_resource_list(list: &str) -> anyhow::Result<BTreeMap<String, Quantity>> {
+    let mut resources = BTreeMap::new();
+    for item in list.split(',').filter(|item| !item.is_empty()) {
+        let mut parts = item.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(quantity)) => {
+                crate::quantity::parse_millis(quantity)?;
+                resources.insert(
+                    name.trim().to_string(),
+                    Quantity(quantity.trim().to_string()),
+                );
+            }
+            _ => anyhow::bail!("Invalid resource {:?}, expected name=quantity.", item),
+        }
+    }
+    Ok(resources)
+}
+
+/// Parses a list such as `memory.available<100Mi,nodefs.available<10%`.
+fn parse_thresholds(list: &str) -> anyhow::Result<BTreeMap<String, Threshold>> {
+    let mut thresholds = BTreeMap::new();
+    for item in list.split(',').filter(|item| !item.is_empty()) {
+        let mut parts = item.splitn(2, '<');
+        let (signal, value) = match (parts.next(), parts.next()) {
+            (Some(signal), Some(value)) => (signal.trim(), value.trim()),
+            _ => anyhow::bail!(
+                "Invalid eviction threshold {:?}, expected signal<value.",
+                item
+            ),
+        };
+        let threshold = if let Some(percentage) = value.strip_suffix('%') {
+            Threshold::Percentage(percentage.parse()?)
+        } else {
+            crate::quantity::parse_millis(value)?;
+            Threshold::Quantity(Quantity(value.to_string()))
+        };
+        thresholds.insert(signal.to_string(), threshold);
+    }
+    Ok(thresholds)
+}
+
+/// Parses a list such as `10.96.0.10,fd00::10`.
+fn parse_ips(list: &str) -> anyhow::Result<Vec<String>> {
+    let mut ips = vec![];
+    for ip in list.split(',').map(str::trim).filter(|ip| !ip.is_empty()) {
+        if ip.parse::<std::net::IpAddr>().is_err() {
+            anyhow::bail!("Invalid IP address {:?}.", ip);
+        }
+        ips.push(ip.to_string());
+    }
+    Ok(ips)
+}
+
+/// Removes the first `--flag value` or `--flag=value` from `args`, returning its value.
+fn take_flag(args: &mut Vec<OsString>, flag: &str) -> anyhow::Result<Option<OsString>> {
+    let prefix = format!("{}=", flag);
+    for i in 1..args.len() {
+        let arg = args[i].to_string_lossy().to_string();
+        if arg == flag {
+            if i + 1 >= args.len() {
+                anyhow::bail!("Flag {} requires a value.", flag);
+            }
+            let value = args.remove(i + 1);
+            args.remove(i);
+            return Ok(Some(value));
+        } else if arg.starts_with(&prefix) {
+            let value = arg[prefix.len()..].into();
+            args.remove(i);
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+/// Removes the first `--flag` or `--flag=value` from `args`, returning its value. A bare flag
+/// means `true`, so the following argument is left alone.
+fn take_bool_flag(args: &mut Vec<OsString>, flag: &str) -> Option<OsString> {
+    let prefix = format!("{}=", flag);
+    for i in 1..args.len() {
+        let arg = args[i].to_string_lossy().to_string();
+        if arg == flag {
+            args.remove(i);
+            return Some("true".into());
+        } else if arg.starts_with(&prefix) {
+            let value = arg[prefix.len()..].into();
+            args.remove(i);
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(5400)));
+        assert_eq!(
+            parse_duration("1m500ms"),
+            Some(Duration::from_millis(60_500))
+        );
+        assert_eq!(parse_duration(" 10s "), Some(Duration::from_secs(10)));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("s"), None);
+        assert_eq!(parse_duration("1d"), None);
+        assert_eq!(parse_duration("-1s"), None);
+    }
+
+    #[test]
+    fn parses_reservations() {
+        let reserved = parse_resource_list("cpu=100m, memory=256Mi").unwrap();
+        assert_eq!(reserved["cpu"], Quantity("100m".to_string()));
+        assert_eq!(reserved["memory"], Quantity("256Mi".to_string()));
+        assert!(parse_resource_list("").unwrap().is_empty());
+        assert!(parse_resource_list("cpu").is_err());
+        assert!(parse_resource_list("cpu=lots").is_err());
+    }
+
+    #[test]
+    fn parses_eviction_thresholds() {
+        let thresholds = parse_thresholds(DEFAULT_EVICTION_HARD).unwrap();
+        assert_eq!(
+            thresholds["memory.available"],
+            Threshold::Quantity(Quantity("100Mi".to_string()))
+        );
+        assert_eq!(thresholds["nodefs.available"], Threshold::Percentage(10.0));
+        assert!(parse_thresholds("memory.available>100Mi").is_err());
+        assert!(parse_thresholds("nodefs.available<ten%").is_err());
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn takes_flags_with_values() {
+        let mut argv = args(&["krustlet-cri", "--cluster-dns", "10.96.0.10", "--port=3000"]);
+        let value = take_flag(&mut argv, "--cluster-dns").unwrap();
+        assert_eq!(value, Some("10.96.0.10".into()));
+        assert_eq!(argv, args(&["krustlet-cri", "--port=3000"]));
+
+        let mut argv = args(&["krustlet-cri", "--cluster-domain=example.com"]);
+        let value = take_flag(&mut argv, "--cluster-domain").unwrap();
+        assert_eq!(value, Some("example.com".into()));
+        assert_eq!(argv, args(&["krustlet-cri"]));
+
+        let mut argv = args(&["krustlet-cri", "--cluster-dns"]);
+        assert!(take_flag(&mut argv, "--cluster-dns").is_err());
+    }
+
+    #[test]
+    fn takes_bare_bool_flags() {
+        let mut argv = args(&["krustlet-cri", "--cpu-cfs-quota", "--port", "3000"]);
+        assert_eq!(
+            take_bool_flag(&mut argv, "--cpu-cfs-quota"),
+            Some("true".into())
+        );
+        assert_eq!(argv, args(&["krustlet-cri", "--port", "3000"]));
+    }
+
+    #[test]
+    fn takes_bool_flags_with_values() {
+        let mut argv = args(&[
+            "krustlet-cri",
+            "--cpu-cfs-quota=false",
+            "--cpu-cfs-quota-period=50ms",
+        ]);
+        assert_eq!(
+            take_bool_flag(&mut argv, "--cpu-cfs-quota"),
+            Some("false".into())
+        );
+        assert_eq!(take_bool_flag(&mut argv, "--cpu-cfs-quota"), None);
+        assert_eq!(argv, args(&["krustlet-cri", "--cpu-cfs-quota-period=50ms"]));
+    }
+}
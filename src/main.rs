@@ -1,11 +1,19 @@
 #![type_length_limit = "1271125"]
+mod config;
+mod node;
+mod pod;
 mod provider;
+mod quantity;
+mod runtime;
 mod states;
+#[cfg(test)]
+mod testing;
 
 use log::debug;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cri_config = config::Config::new_from_flags()?;
     let config = kubelet::config::Config::new_from_flags(env!("CARGO_PKG_VERSION"));
 
     env_logger::init();
@@ -15,13 +23,13 @@ async fn main() -> anyhow::Result<()> {
         kubelet::bootstrap(&config, &config.bootstrap_file, |s| println!("{}", s)).await?;
 
     debug!("Creating Provider.");
-    let provider = provider::Provider::new_from_socket_address(
-        "/run/containerd/containerd.sock",
-        kubeconfig.clone(),
-    );
+    let provider = provider::Provider::new(cri_config, kubeconfig.clone(), &config);
+
+    debug!("Starting API proxy.");
+    let kubelet_config = node::proxy::start(kubeconfig, &config.node_name, provider.health())?;
 
     debug!("Creating Kubelet.");
-    let kubelet = kubelet::Kubelet::new(provider, kubeconfig, config).await?;
+    let kubelet = kubelet::Kubelet::new(provider, kubelet_config, config).await?;
 
     debug!("Running.");
     kubelet.start().await
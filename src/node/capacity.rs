@@ -0,0 +1,368 @@
+use k8s_cri::v1alpha2 as cri;
+use log::{debug, warn};
+use std::collections::BTreeMap;
+
+use crate::config::{Config, Threshold};
+use crate::runtime::Connection;
+
+/// Node resources, in thousandths of their unit.
+pub type Resources = BTreeMap<String, i128>;
+
+/// Eviction signals and the resources they are measured against.
+const EVICTION_SIGNALS: &[(&str, &str)] = &[
+    ("memory.available", "memory"),
+    ("nodefs.available", "ephemeral-storage"),
+];
+
+/// Reads node capacity from `/proc`, cgroups and the runtime's image filesystem.
+pub async fn detect(max_pods: u16, image: &Connection) -> Resources {
+    let mut capacity = Resources::new();
+    if let Some(cpus) = cpu_capacity().await {
+        capacity.insert("cpu".to_string(), cpus);
+    }
+    if let Some(memory) = memory_capacity().await {
+        capacity.insert("memory".to_string(), memory * 1000);
+    }
+    if let Some(storage) = ephemeral_storage_capacity(image).await {
+        capacity.insert("ephemeral-storage".to_string(), storage * 1000);
+    }
+    capacity.extend(
+        hugepages_capacity()
+            .await
+            .into_iter()
+            .map(|(name, bytes)| (name, bytes * 1000)),
+    );
+    capacity.insert("pods".to_string(), i128::from(max_pods) * 1000);
+    capacity
+}
+
+/// Subtracts reservations and hard eviction thresholds from capacity.
+pub fn allocatable(capacity: &Resources, config: &Config) -> anyhow::Result<Resources> {
+    let mut allocatable = capacity.clone();
+    for reserved in &[&config.kube_reserved, &config.system_reserved] {
+        for (name, quantity) in reserved.iter() {
+            if let Some(value) = allocatable.get_mut(name) {
+                *value -= crate::quantity::milli_value(quantity)?;
+            }
+        }
+    }
+    for (signal, resource) in EVICTION_SIGNALS {
+        let threshold = match config.eviction_hard.get(*signal) {
+            Some(threshold) => threshold,
+            None => continue,
+        };
+        if let Some(value) = allocatable.get_mut(*resource) {
+            *value -= match threshold {
+                Threshold::Quantity(quantity) => crate::quantity::milli_value(quantity)?,
+                Threshold::Percentage(percentage) => {
+                    (capacity[*resource] as f64 * percentage / 100.0) as i128
+                }
+            };
+        }
+    }
+    for value in allocatable.values_mut() {
+        *value = std::cmp::max(*value, 0);
+    }
+    Ok(allocatable)
+}
+
+/// Online CPUs, limited by the CFS quota of KrustletCRI's cgroup if one is set.
+async fn cpu_capacity() -> Option<i128> {
+    let cpus = processors(&read("/proc/cpuinfo").await?);
+    // cgroup v2 exposes "<quota> <period>", v1 splits them into two files.
+    let (quota, period) = match read("/sys/fs/cgroup/cpu.max").await {
+        Some(max) => parse_cpu_max(&max),
+        None => (
+            read("/sys/fs/cgroup/cpu/cpu.cfs_quota_us")
+                .await
+                .and_then(|quota| quota.parse::<i128>().ok()),
+            read("/sys/fs/cgroup/cpu/cpu.cfs_period_us")
+                .await
+                .and_then(|period| period.parse::<i128>().ok()),
+        ),
+    };
+    Some(limit_cpus(cpus, quota, period))
+}
+
+/// Thousandths of a CPU for each processor listed in `/proc/cpuinfo`.
+fn processors(cpuinfo: &str) -> i128 {
+    cpuinfo
+        .lines()
+        .filter(|line| line.starts_with("processor"))
+        .count() as i128
+        * 1000
+}
+
+/// Parses the quota and period of a cgroup v2 `cpu.max`, where the quota may be `max`.
+fn parse_cpu_max(max: &str) -> (Option<i128>, Option<i128>) {
+    let mut parts = max.split_whitespace();
+    (
+        parts.next().and_then(|quota| quota.parse::<i128>().ok()),
+        parts.next().and_then(|period| period.parse::<i128>().ok()),
+    )
+}
+
+/// Limits thousandths of CPUs to a CFS quota, which is negative in cgroup v1 when unset.
+fn limit_cpus(cpus: i128, quota: Option<i128>, period: Option<i128>) -> i128 {
+    match (quota, period) {
+        (Some(quota), Some(period)) if quota > 0 && period > 0 => {
+            std::cmp::min(cpus, quota * 1000 / period)
+        }
+        _ => cpus,
+    }
+}
+
+/// Bytes of memory, limited by KrustletCRI's cgroup if one is set.
+pub async fn memory_capacity() -> Option<i128> {
+    let total = mem_total(&read("/proc/meminfo").await?)?;
+    let limit = match read("/sys/fs/cgroup/memory.max").await {
+        Some(limit) => limit,
+        None => read("/sys/fs/cgroup/memory/memory.limit_in_bytes").await?,
+    };
+    Some(limit_memory(total, &limit))
+}
+
+/// Limits bytes of memory to a cgroup limit, which cgroup v2 reports as `max` when unset.
+fn limit_memory(total: i128, limit: &str) -> i128 {
+    match limit.parse::<i128>() {
+        Ok(limit) => std::cmp::min(total, limit),
+        Err(_) => total,
+    }
+}
+
+/// Bytes of `MemTotal` in `/proc/meminfo`, which is given in KiB.
+fn mem_total(meminfo: &str) -> Option<i128> {
+    Some(
+        meminfo
+            .lines()
+            .find(|line| line.starts_with("MemTotal:"))?
+            .split_whitespace()
+            .nth(1)?
+            .parse::<i128>()
+            .ok()?
+            * 1024,
+    )
+}
+
+/// Size in bytes of the filesystem holding the runtime's images.
+async fn ephemeral_storage_capacity(image: &Connection) -> Option<i128> {
+    let mountpoint = match image_fs_mountpoint(image).await {
+        Ok(Some(mountpoint)) => mountpoint,
+        Ok(None) => "/".to_string(),
+        Err(e) => {
+            warn!("Error finding image filesystem: {:?}", &e);
+            "/".to_string()
+        }
+    };
+    match filesystem_size(&mountpoint) {
+        Ok(size) => Some(size),
+        Err(e) => {
+            warn!("Error reading size of {}: {:?}", &mountpoint, &e);
+            None
+        }
+    }
+}
+
+async fn image_fs_mountpoint(image: &Connection) -> anyhow::Result<Option<String>> {
+    let mut client = image.image_client().await?;
+    let request = tonic::Request::new(cri::ImageFsInfoRequest {});
+    debug!("Sending request: {:?}", &request);
+    let response = client.image_fs_info(request).await?;
+    Ok(response
+        .image_filesystems
+        .into_iter()
+        .filter_map(|usage| usage.fs_id)
+        .map(|id| id.mountpoint)
+        .find(|mountpoint| !mountpoint.is_empty()))
+}
+
+fn filesystem_size(path: &str) -> anyhow::Result<i128> {
+    let path = std::ffi::CString::new(path)?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        anyhow::bail!(std::io::Error::last_os_error());
+    }
+    Ok(i128::from(stat.f_blocks) * i128::from(stat.f_frsize))
+}
+
+/// Bytes of preallocated hugepages, keyed by resource name such as `hugepages-2Mi`.
+async fn hugepages_capacity() -> Vec<(String, i128)> {
+    let mut capacity = vec![];
+    let mut entries = match tokio::fs::read_dir("/sys/kernel/mm/hugepages").await {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("Could not list hugepages: {:?}", &e);
+            return capacity;
+        }
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        // Directories are named like "hugepages-2048kB".
+        let name = entry.file_name().to_string_lossy().to_string();
+        let size_kb = match name
+            .trim_start_matches("hugepages-")
+            .trim_end_matches("kB")
+            .parse::<i128>()
+        {
+            Ok(size_kb) => size_kb,
+            Err(_) => continue,
+        };
+        let pages = match read(&format!("{}/nr_hugepages", entry.path().display()))
+            .await
+            .and_then(|pages| pages.parse::<i128>().ok())
+        {
+            Some(pages) => pages,
+            None => continue,
+        };
+        capacity.push((hugepage_resource(size_kb), pages * size_kb * 1024));
+    }
+    capacity
+}
+
+fn hugepage_resource(size_kb: i128) -> String {
+    if size_kb % (1024 * 1024) == 0 {
+        format!("hugepages-{}Gi", size_kb / (1024 * 1024))
+    } else if size_kb % 1024 == 0 {
+        format!("hugepages-{}Mi", size_kb / 1024)
+    } else {
+        format!("hugepages-{}Ki", size_kb)
+    }
+}
+
+async fn read(path: &str) -> Option<String> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(contents) => Some(contents.trim().to_string()),
+        Err(e) => {
+            debug!("Could not read {}: {:?}", path, &e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+
+    const GI: i128 = 1024 * 1024 * 1024 * 1000;
+
+    fn capacity() -> Resources {
+        vec![
+            ("cpu", 4000),
+            ("memory", 8 * GI),
+            ("ephemeral-storage", 100 * GI),
+            ("pods", 110_000),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+    }
+
+    fn config(
+        kube_reserved: &[(&str, &str)],
+        system_reserved: &[(&str, &str)],
+        eviction_hard: &[(&str, Threshold)],
+    ) -> Config {
+        let quantities = |list: &[(&str, &str)]| {
+            list.iter()
+                .map(|(name, quantity)| (name.to_string(), Quantity(quantity.to_string())))
+                .collect()
+        };
+        Config {
+            kube_reserved: quantities(kube_reserved),
+            system_reserved: quantities(system_reserved),
+            eviction_hard: eviction_hard
+                .iter()
+                .map(|(signal, threshold)| (signal.to_string(), threshold.clone()))
+                .collect(),
+            ..crate::testing::config()
+        }
+    }
+
+    #[test]
+    fn subtracts_reservations() {
+        let config = config(
+            &[("cpu", "500m"), ("memory", "1Gi")],
+            &[("cpu", "250m"), ("hugepages-2Mi", "1Gi")],
+            &[],
+        );
+        let allocatable = allocatable(&capacity(), &config).unwrap();
+        assert_eq!(allocatable["cpu"], 3250);
+        assert_eq!(allocatable["memory"], 7 * GI);
+        assert_eq!(allocatable["pods"], 110_000);
+        // Reservations of resources the node does not have are ignored.
+        assert!(!allocatable.contains_key("hugepages-2Mi"));
+    }
+
+    #[test]
+    fn subtracts_eviction_thresholds() {
+        let config = config(
+            &[("memory", "1Gi")],
+            &[],
+            &[
+                (
+                    "memory.available",
+                    Threshold::Quantity(Quantity("1Gi".to_string())),
+                ),
+                ("nodefs.available", Threshold::Percentage(10.0)),
+                ("imagefs.available", Threshold::Percentage(15.0)),
+            ],
+        );
+        let allocatable = allocatable(&capacity(), &config).unwrap();
+        assert_eq!(allocatable["memory"], 6 * GI);
+        assert_eq!(allocatable["ephemeral-storage"], 90 * GI);
+        assert_eq!(allocatable["cpu"], 4000);
+    }
+
+    #[test]
+    fn allocatable_is_not_negative() {
+        let config = config(&[("cpu", "8")], &[], &[]);
+        assert_eq!(allocatable(&capacity(), &config).unwrap()["cpu"], 0);
+    }
+
+    #[test]
+    fn reads_cpus() {
+        let cpuinfo = "processor\t: 0\nmodel name\t: Xeon\n\nprocessor\t: 1\nmodel name\t: Xeon\n";
+        assert_eq!(processors(cpuinfo), 2000);
+    }
+
+    #[test]
+    fn limits_cpus_to_the_cgroup_quota() {
+        assert_eq!(
+            parse_cpu_max("150000 100000"),
+            (Some(150_000), Some(100_000))
+        );
+        assert_eq!(parse_cpu_max("max 100000"), (None, Some(100_000)));
+
+        let (quota, period) = parse_cpu_max("150000 100000");
+        assert_eq!(limit_cpus(4000, quota, period), 1500);
+        let (quota, period) = parse_cpu_max("max 100000");
+        assert_eq!(limit_cpus(4000, quota, period), 4000);
+        // cgroup v1 reports an unlimited quota as -1.
+        assert_eq!(limit_cpus(4000, Some(-1), Some(100_000)), 4000);
+        // A quota above the number of CPUs does not add any.
+        assert_eq!(limit_cpus(4000, Some(800_000), Some(100_000)), 4000);
+    }
+
+    #[test]
+    fn reads_memory() {
+        let meminfo = "MemTotal:        8048576 kB\nMemFree:         1048576 kB\n";
+        assert_eq!(mem_total(meminfo), Some(8_048_576 * 1024));
+        assert_eq!(mem_total("MemFree: 1 kB\n"), None);
+    }
+
+    #[test]
+    fn limits_memory_to_the_cgroup() {
+        let total = 8 << 30;
+        assert_eq!(limit_memory(total, "1073741824"), 1 << 30);
+        assert_eq!(limit_memory(total, "max"), total);
+        // cgroup v1 reports an unlimited limit as a huge number.
+        assert_eq!(limit_memory(total, "9223372036854771712"), total);
+    }
+
+    #[test]
+    fn names_hugepages() {
+        assert_eq!(hugepage_resource(2048), "hugepages-2Mi");
+        assert_eq!(hugepage_resource(1024 * 1024), "hugepages-1Gi");
+        assert_eq!(hugepage_resource(64), "hugepages-64Ki");
+    }
+}
@@ -0,0 +1,146 @@
+use log::debug;
+use std::path::Path;
+
+/// Kubernetes name of the architecture KrustletCRI was built for.
+#[cfg(target_arch = "x86_64")]
+pub const ARCH: &str = "amd64";
+#[cfg(target_arch = "aarch64")]
+pub const ARCH: &str = "arm64";
+#[cfg(target_arch = "arm")]
+pub const ARCH: &str = "arm";
+#[cfg(target_arch = "x86")]
+pub const ARCH: &str = "386";
+#[cfg(all(target_arch = "powerpc64", target_endian = "little"))]
+pub const ARCH: &str = "ppc64le";
+#[cfg(target_arch = "s390x")]
+pub const ARCH: &str = "s390x";
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "arm",
+    target_arch = "x86",
+    all(target_arch = "powerpc64", target_endian = "little"),
+    target_arch = "s390x"
+)))]
+pub const ARCH: &str = std::env::consts::ARCH;
+
+/// Kubernetes name of the operating system KrustletCRI was built for.
+pub const OS: &str = std::env::consts::OS;
+
+/// Host details published in the Node's `status.nodeInfo`.
+#[derive(Clone, Debug, Default)]
+pub struct NodeInfo {
+    pub kernel_version: String,
+    pub os_image: String,
+    pub machine_id: String,
+    pub boot_id: String,
+    pub system_uuid: String,
+}
+
+impl NodeInfo {
+    pub async fn detect() -> Self {
+        Self::read(Path::new("/")).await
+    }
+
+    /// Reads the host files under `root`.
+    async fn read(root: &Path) -> Self {
+        let os_release = read_trimmed(root, "etc/os-release").await;
+        NodeInfo {
+            kernel_version: read_trimmed(root, "proc/sys/kernel/osrelease").await,
+            os_image: parse_os_release(&os_release, "PRETTY_NAME")
+                .unwrap_or_else(|| "Linux".to_string()),
+            machine_id: read_trimmed(root, "etc/machine-id").await,
+            boot_id: read_trimmed(root, "proc/sys/kernel/random/boot_id").await,
+            system_uuid: read_trimmed(root, "sys/class/dmi/id/product_uuid").await,
+        }
+    }
+
+    /// Fields of `status.nodeInfo` which the Kubelet does not already fill in.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "architecture": ARCH,
+            "operatingSystem": OS,
+            "kernelVersion": &self.kernel_version,
+            "osImage": &self.os_image,
+            "machineID": &self.machine_id,
+            "bootID": &self.boot_id,
+            "systemUUID": &self.system_uuid,
+        })
+    }
+}
+
+/// Reads a host file, returning an empty string if it is unavailable.
+async fn read_trimmed(root: &Path, path: &str) -> String {
+    let path = root.join(path);
+    match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => contents.trim().to_string(),
+        Err(e) => {
+            debug!("Could not read {}: {:?}", path.display(), &e);
+            String::new()
+        }
+    }
+}
+
+fn parse_os_release(contents: &str, key: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let mut parts = line.splitn(2, '=');
+        if parts.next()? == key {
+            Some(parts.next()?.trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OS_RELEASE: &str = r#"NAME="Ubuntu"
+VERSION_ID="20.04"
+PRETTY_NAME="Ubuntu 20.04.1 LTS"
+ID=ubuntu
+"#;
+
+    #[test]
+    fn parses_os_release() {
+        assert_eq!(
+            parse_os_release(OS_RELEASE, "PRETTY_NAME").unwrap(),
+            "Ubuntu 20.04.1 LTS"
+        );
+        assert_eq!(parse_os_release(OS_RELEASE, "ID").unwrap(), "ubuntu");
+        assert_eq!(parse_os_release(OS_RELEASE, "NAME").unwrap(), "Ubuntu");
+        assert_eq!(parse_os_release(OS_RELEASE, "VERSION"), None);
+    }
+
+    #[tokio::test]
+    async fn reads_host_files() {
+        let root = std::env::temp_dir().join(format!("krustlet-cri-info-{}", std::process::id()));
+        for (path, contents) in &[
+            ("etc/os-release", OS_RELEASE),
+            ("etc/machine-id", "0123456789abcdef\n"),
+            ("proc/sys/kernel/osrelease", "5.4.0-48-generic\n"),
+            ("proc/sys/kernel/random/boot_id", "4f1e7d2a-boot\n"),
+        ] {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        let info = NodeInfo::read(&root).await;
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(info.kernel_version, "5.4.0-48-generic");
+        assert_eq!(info.os_image, "Ubuntu 20.04.1 LTS");
+        assert_eq!(info.machine_id, "0123456789abcdef");
+        assert_eq!(info.boot_id, "4f1e7d2a-boot");
+        // Missing files are reported empty rather than failing detection.
+        assert_eq!(info.system_uuid, "");
+    }
+
+    #[tokio::test]
+    async fn defaults_the_os_image() {
+        let info = NodeInfo::read(Path::new("/nonexistent")).await;
+        assert_eq!(info.os_image, "Linux");
+        assert_eq!(info.kernel_version, "");
+    }
+}
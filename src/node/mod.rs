@@ -0,0 +1,4 @@
+//! Registration and status of the Kubernetes Node.
+pub mod capacity;
+pub mod info;
+pub mod proxy;
@@ -0,0 +1,299 @@
+//! A local proxy between the Kubelet and the API server, which makes the runtime [`Watchdog`]
+//! the only writer of the node's conditions.
+//!
+//! The Kubelet creates the node as `Ready` and patches `Ready=True` on every heartbeat, using a
+//! merge patch which replaces all other conditions. Neither can be configured, so the Kubelet's
+//! requests are routed through this proxy. It creates the node with the watchdog's conditions
+//! and removes the conditions from heartbeats, which then only renew the node lease. Every other
+//! request is forwarded unchanged.
+
+use hyper::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Method, Request, Response, StatusCode};
+use k8s_openapi::api::core::v1::NodeCondition;
+use log::{debug, error};
+use std::convert::Infallible;
+use std::io::Read;
+use std::sync::Arc;
+use tokio::stream::StreamExt;
+
+use crate::runtime::Watchdog;
+
+struct Proxy {
+    client: kube::Client,
+    /// Required of every request, so that other local processes cannot use the Kubelet's
+    /// credentials through the proxy.
+    authorization: String,
+    status_path: String,
+    health: Watchdog,
+}
+
+/// Starts the proxy in the background, and returns the configuration the Kubelet should use to
+/// reach the API server through it.
+pub fn start(
+    kubeconfig: kube::Config,
+    node_name: &str,
+    health: Watchdog,
+) -> anyhow::Result<kube::Config> {
+    let timeout = kubeconfig.timeout;
+    let proxy = Arc::new(Proxy {
+        client: kube::Client::new(kubeconfig),
+        authorization: format!("Bearer {}", token()?),
+        status_path: format!("/api/v1/nodes/{}/status", node_name),
+        health,
+    });
+    let mut headers = HeaderMap::new();
+    headers.insert(AUTHORIZATION, proxy.authorization.parse()?);
+
+    let make_service = make_service_fn(move |_| {
+        let proxy = proxy.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let proxy = proxy.clone();
+                async move { Ok::<_, Infallible>(proxy.forward(request).await) }
+            }))
+        }
+    });
+    let server = hyper::Server::try_bind(&([127, 0, 0, 1], 0).into())?.serve(make_service);
+    let url = format!("http://{}", server.local_addr()).parse()?;
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("API proxy failed: {:?}", e);
+        }
+    });
+
+    let mut config = kube::Config::new(url);
+    config.headers = headers;
+    config.timeout = timeout;
+    Ok(config)
+}
+
+impl Proxy {
+    async fn forward(&self, request: Request<Body>) -> Response<Body> {
+        let authorized = request
+            .headers()
+            .get(AUTHORIZATION)
+            .is_some_and(|value| value == self.authorization.as_str());
+        if !authorized {
+            return status(StatusCode::UNAUTHORIZED, "Unauthorized", "Unauthorized");
+        }
+
+        let (parts, body) = request.into_parts();
+        let body = match hyper::body::to_bytes(body).await {
+            Ok(body) => body.to_vec(),
+            Err(e) => return status(StatusCode::BAD_REQUEST, "BadRequest", &e.to_string()),
+        };
+        let body = if parts.method == Method::POST && parts.uri.path() == "/api/v1/nodes" {
+            set_conditions(body, Some(self.health.conditions().await))
+        } else if parts.method == Method::PATCH && parts.uri.path() == self.status_path {
+            set_conditions(body, None)
+        } else {
+            body
+        };
+
+        let path = parts
+            .uri
+            .path_and_query()
+            .map_or("/", |path| path.as_str())
+            .to_string();
+        let mut outgoing = Request::builder().method(parts.method).uri(&path);
+        for header in &[ACCEPT, CONTENT_TYPE] {
+            if let Some(value) = parts.headers.get(header) {
+                outgoing = outgoing.header(header, value);
+            }
+        }
+        let outgoing = match outgoing.body(body) {
+            Ok(outgoing) => outgoing,
+            Err(e) => return status(StatusCode::BAD_REQUEST, "BadRequest", &e.to_string()),
+        };
+        debug!("Proxying {} {}", outgoing.method(), &path);
+
+        let watch = parts
+            .uri
+            .query()
+            .is_some_and(|query| query.split('&').any(|param| param == "watch=true"));
+        if watch {
+            self.stream(outgoing).await
+        } else {
+            match self.client.request_text(outgoing).await {
+                Ok(text) => json(StatusCode::OK, text),
+                Err(kube::Error::Api(e)) => status(
+                    StatusCode::from_u16(e.code).unwrap_or(StatusCode::BAD_GATEWAY),
+                    &e.reason,
+                    &e.message,
+                ),
+                Err(e) => status(StatusCode::BAD_GATEWAY, "BadGateway", &e.to_string()),
+            }
+        }
+    }
+
+    /// Forwards a watch, whose events are passed on as they arrive.
+    async fn stream(&self, outgoing: Request<Vec<u8>>) -> Response<Body> {
+        let events = match self.client.request_text_stream(outgoing).await {
+            Ok(events) => events,
+            Err(e) => return status(StatusCode::BAD_GATEWAY, "BadGateway", &e.to_string()),
+        };
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            tokio::pin!(events);
+            while let Some(chunk) = events.next().await {
+                match chunk {
+                    Ok(chunk) => {
+                        if sender.send_data(chunk).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        debug!("Watch ended with error: {:?}", &e);
+                        sender.abort();
+                        return;
+                    }
+                }
+            }
+        });
+        Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .unwrap()
+    }
+}
+
+/// Sets `status.conditions` of a node or node status patch, or removes them if `conditions` is
+/// `None`. Bodies which are not JSON are returned as they are.
+fn set_conditions(body: Vec<u8>, conditions: Option<Vec<NodeCondition>>) -> Vec<u8> {
+    let mut node: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(node) => node,
+        Err(_) => return body,
+    };
+    if let Some(status) = node
+        .get_mut("status")
+        .and_then(|status| status.as_object_mut())
+    {
+        match conditions {
+            Some(conditions) => {
+                status.insert("conditions".to_string(), serde_json::json!(conditions))
+            }
+            None => status.remove("conditions"),
+        };
+    }
+    serde_json::to_vec(&node).unwrap_or(body)
+}
+
+fn json(code: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(code)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// A failed API `Status`, which the Kubelet's client turns back into an API error.
+fn status(code: StatusCode, reason: &str, message: &str) -> Response<Body> {
+    let status = serde_json::json!({
+        "kind": "Status",
+        "apiVersion": "v1",
+        "metadata": {},
+        "status": "Failure",
+        "message": message,
+        "reason": reason,
+        "code": code.as_u16(),
+    });
+    json(code, status.to_string())
+}
+
+fn token() -> std::io::Result<String> {
+    let mut bytes = [0u8; 16];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ready() -> NodeCondition {
+        NodeCondition {
+            type_: "Ready".to_string(),
+            status: "False".to_string(),
+            reason: Some("KubeletNotReady".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn body(value: serde_json::Value) -> Vec<u8> {
+        serde_json::to_vec(&value).unwrap()
+    }
+
+    #[test]
+    fn creates_the_node_with_the_watchdog_conditions() {
+        let node = body(json!({
+            "metadata": {"name": "node-1"},
+            "status": {
+                "conditions": [{"type": "Ready", "status": "True"}],
+                "nodeInfo": {"architecture": "amd64"},
+            },
+        }));
+        let node: serde_json::Value =
+            serde_json::from_slice(&set_conditions(node, Some(vec![ready()]))).unwrap();
+        assert_eq!(
+            node["status"]["conditions"],
+            json!([{"type": "Ready", "status": "False", "reason": "KubeletNotReady"}])
+        );
+        assert_eq!(node["status"]["nodeInfo"]["architecture"], "amd64");
+    }
+
+    #[test]
+    fn removes_the_conditions_from_heartbeats() {
+        let patch = body(json!({
+            "status": {"conditions": [{"type": "Ready", "status": "True"}]},
+        }));
+        let patch: serde_json::Value =
+            serde_json::from_slice(&set_conditions(patch, None)).unwrap();
+        assert_eq!(patch, json!({"status": {}}));
+    }
+
+    #[test]
+    fn passes_other_bodies_through() {
+        let patch = b"not json".to_vec();
+        assert_eq!(set_conditions(patch.clone(), None), patch);
+        let patch = body(json!({"metadata": {"labels": {"a": "b"}}}));
+        assert_eq!(set_conditions(patch.clone(), Some(vec![ready()])), patch);
+    }
+
+    #[tokio::test]
+    async fn forwards_requests_with_the_token() {
+        use crate::testing::api_config;
+        use k8s_openapi::api::core::v1::Node;
+
+        let upstream = api_config(vec![(
+            "/api/v1/nodes/node-1",
+            200,
+            json!({"apiVersion": "v1", "kind": "Node", "metadata": {"name": "node-1"}}),
+        )]);
+        let config = start(upstream, "node-1", Watchdog::default()).unwrap();
+
+        let nodes: kube::Api<Node> = kube::Api::all(kube::Client::new(config.clone()));
+        let node = nodes.get("node-1").await.unwrap();
+        assert_eq!(node.metadata.name.unwrap(), "node-1");
+        match nodes.get("node-2").await {
+            Err(kube::Error::Api(e)) => assert_eq!((e.code, e.reason.as_str()), (404, "NotFound")),
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let mut anonymous = config;
+        anonymous.headers.clear();
+        let nodes: kube::Api<Node> = kube::Api::all(kube::Client::new(anonymous));
+        match nodes.get("node-1").await {
+            Err(kube::Error::Api(e)) => assert_eq!(e.code, 401),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn generates_distinct_tokens() {
+        let token = token().unwrap();
+        assert_eq!(token.len(), 32);
+        assert_ne!(token, super::token().unwrap());
+    }
+}
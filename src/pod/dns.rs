@@ -0,0 +1,360 @@
+use k8s_cri::v1alpha2 as cri;
+use k8s_openapi::api::core::v1::{Pod as KubePod, PodDNSConfig};
+use log::{debug, warn};
+
+use super::namespaces;
+use crate::config::Config;
+
+/// Limits imposed by the resolver on resolv.conf, matching those enforced by the Kubelet.
+const MAX_NAMESERVERS: usize = 3;
+const MAX_SEARCH_PATHS: usize = 6;
+const MAX_SEARCH_LIST_CHARS: usize = 256;
+
+/// Where a pod's resolver configuration comes from.
+#[derive(Debug, PartialEq)]
+enum Source {
+    /// The cluster DNS service.
+    Cluster,
+    /// The node's resolv.conf.
+    Host,
+    /// Only `spec.dnsConfig`.
+    None,
+}
+
+/// Resolver configuration read from a resolv.conf file.
+#[derive(Debug, Default)]
+struct ResolvConf {
+    servers: Vec<String>,
+    searches: Vec<String>,
+    options: Vec<String>,
+}
+
+/// Builds the sandbox DNS configuration for `spec.dnsPolicy` and `spec.dnsConfig`.
+pub async fn dns_config(pod: &KubePod, config: &Config) -> anyhow::Result<cri::DnsConfig> {
+    let spec = pod.spec.clone().unwrap_or_default();
+    let namespace = pod.metadata.namespace.as_deref().unwrap_or("default");
+
+    let source = match spec.dns_policy.as_deref().unwrap_or("ClusterFirst") {
+        // Pods using the host network keep the host's resolver unless they opt in.
+        "ClusterFirst" if namespaces::host_network(pod) => Source::Host,
+        "ClusterFirst" | "ClusterFirstWithHostNet" => Source::Cluster,
+        "Default" => Source::Host,
+        "None" => {
+            if spec.dns_config.is_none() {
+                anyhow::bail!("dnsPolicy None requires dnsConfig.");
+            }
+            Source::None
+        }
+        policy => anyhow::bail!("Unsupported dnsPolicy {}.", policy),
+    };
+    let source = if source == Source::Cluster && config.cluster_dns.is_empty() {
+        warn!(
+            "No cluster DNS IP configured, falling back to Default dnsPolicy for pod {}/{}.",
+            namespace,
+            pod.metadata.name.as_deref().unwrap_or_default()
+        );
+        Source::Host
+    } else {
+        source
+    };
+
+    let host = match source {
+        Source::None => ResolvConf::default(),
+        _ => read_resolv_conf(&config.resolv_conf).await,
+    };
+    let mut dns_config = match source {
+        Source::Cluster => {
+            let mut searches = vec![];
+            if !config.cluster_domain.is_empty() {
+                searches.push(format!("{}.svc.{}", namespace, &config.cluster_domain));
+                searches.push(format!("svc.{}", &config.cluster_domain));
+                searches.push(config.cluster_domain.clone());
+            }
+            searches.extend(host.searches);
+            cri::DnsConfig {
+                servers: config.cluster_dns.clone(),
+                searches,
+                options: vec!["ndots:5".to_string()],
+            }
+        }
+        Source::Host | Source::None => cri::DnsConfig {
+            servers: host.servers,
+            searches: host.searches,
+            options: host.options,
+        },
+    };
+
+    if let Some(pod_dns_config) = &spec.dns_config {
+        merge(&mut dns_config, pod_dns_config);
+    }
+    if source == Source::None && dns_config.servers.is_empty() {
+        anyhow::bail!("dnsPolicy None requires at least one nameserver in dnsConfig.");
+    }
+    fit_limits(&mut dns_config);
+    debug!("DNS config: {:?}", &dns_config);
+    Ok(dns_config)
+}
+
+/// Appends `spec.dnsConfig` to the policy's configuration. Options are merged by name, with
+/// those from the pod taking precedence.
+fn merge(dns_config: &mut cri::DnsConfig, pod_dns_config: &PodDNSConfig) {
+    for server in pod_dns_config.nameservers.iter().flatten() {
+        if !dns_config.servers.contains(server) {
+            dns_config.servers.push(server.clone());
+        }
+    }
+    for search in pod_dns_config.searches.iter().flatten() {
+        if !dns_config.searches.contains(search) {
+            dns_config.searches.push(search.clone());
+        }
+    }
+    for option in pod_dns_config.options.iter().flatten() {
+        let name = match &option.name {
+            Some(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+        let option_string = match &option.value {
+            Some(value) => format!("{}:{}", name, value),
+            None => name.clone(),
+        };
+        dns_config
+            .options
+            .retain(|existing| existing.split(':').next() != Some(name.as_str()));
+        dns_config.options.push(option_string);
+    }
+}
+
+/// Drops nameservers and search paths the resolver would ignore.
+fn fit_limits(dns_config: &mut cri::DnsConfig) {
+    if dns_config.servers.len() > MAX_NAMESERVERS {
+        warn!(
+            "Nameserver limit of {} exceeded, dropping {:?}.",
+            MAX_NAMESERVERS,
+            &dns_config.servers[MAX_NAMESERVERS..]
+        );
+        dns_config.servers.truncate(MAX_NAMESERVERS);
+    }
+    if dns_config.searches.len() > MAX_SEARCH_PATHS {
+        warn!(
+            "Search path limit of {} exceeded, dropping {:?}.",
+            MAX_SEARCH_PATHS,
+            &dns_config.searches[MAX_SEARCH_PATHS..]
+        );
+        dns_config.searches.truncate(MAX_SEARCH_PATHS);
+    }
+    while dns_config.searches.join(" ").len() > MAX_SEARCH_LIST_CHARS {
+        let dropped = dns_config.searches.pop();
+        warn!(
+            "Search list limit of {} characters exceeded, dropping {:?}.",
+            MAX_SEARCH_LIST_CHARS, dropped
+        );
+    }
+}
+
+/// Reads the node's resolv.conf. An empty path means the node has no resolver configuration.
+///
+/// As with the Kubelet, a missing or unreadable file is logged and treated as empty, so pods
+/// still start with the cluster DNS or their own `dnsConfig`.
+async fn read_resolv_conf(path: &str) -> ResolvConf {
+    let mut resolv_conf = ResolvConf::default();
+    if path.is_empty() {
+        return resolv_conf;
+    }
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) => {
+            warn!(
+                "Error reading {}, using no host DNS configuration: {}",
+                path, e
+            );
+            return resolv_conf;
+        }
+    };
+    for line in contents.lines() {
+        let line = line.split(['#', ';']).next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("nameserver") => resolv_conf.servers.extend(fields.next().map(String::from)),
+            // As with the resolver, the last search line wins.
+            Some("search") => resolv_conf.searches = fields.map(String::from).collect(),
+            Some("options") => resolv_conf.options.extend(fields.map(String::from)),
+            _ => (),
+        }
+    }
+    resolv_conf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pod(spec: serde_json::Value) -> KubePod {
+        crate::testing::kube_pod(spec)
+    }
+
+    /// A host resolv.conf in a temp file unique to one test, removed when dropped.
+    struct HostResolvConf(std::path::PathBuf);
+
+    impl HostResolvConf {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "krustlet-cri-{}-resolv-{}.conf",
+                std::process::id(),
+                name
+            ));
+            std::fs::write(
+                &path,
+                "nameserver 192.168.0.1 # router\nsearch old.example\nsearch example.com\noptions edns0\n",
+            )
+            .unwrap();
+            HostResolvConf(path)
+        }
+
+        /// Configuration with `cluster_dns` and this resolv.conf.
+        fn config(&self, cluster_dns: &[&str]) -> Config {
+            Config {
+                cluster_dns: cluster_dns.iter().map(|ip| ip.to_string()).collect(),
+                cluster_domain: "cluster.local".to_string(),
+                resolv_conf: self.0.to_string_lossy().into_owned(),
+                ..crate::testing::config()
+            }
+        }
+    }
+
+    impl Drop for HostResolvConf {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn cluster_first_uses_cluster_dns() {
+        let resolv_conf = HostResolvConf::new("cluster-first");
+        let config = resolv_conf.config(&["10.96.0.10"]);
+        let dns_config = dns_config(&pod(serde_json::json!({})), &config)
+            .await
+            .unwrap();
+        assert_eq!(dns_config.servers, vec!["10.96.0.10"]);
+        assert_eq!(
+            dns_config.searches,
+            vec![
+                "ns.svc.cluster.local",
+                "svc.cluster.local",
+                "cluster.local",
+                "example.com"
+            ]
+        );
+        assert_eq!(dns_config.options, vec!["ndots:5"]);
+    }
+
+    #[tokio::test]
+    async fn host_network_pods_use_host_resolver() {
+        let resolv_conf = HostResolvConf::new("host-network");
+        let config = resolv_conf.config(&["10.96.0.10"]);
+        let host = dns_config(&pod(serde_json::json!({ "hostNetwork": true })), &config)
+            .await
+            .unwrap();
+        assert_eq!(host.servers, vec!["192.168.0.1"]);
+        assert_eq!(host.searches, vec!["example.com"]);
+        assert_eq!(host.options, vec!["edns0"]);
+
+        let cluster = dns_config(
+            &pod(serde_json::json!({
+                "hostNetwork": true,
+                "dnsPolicy": "ClusterFirstWithHostNet",
+            })),
+            &config,
+        )
+        .await
+        .unwrap();
+        assert_eq!(cluster.servers, vec!["10.96.0.10"]);
+    }
+
+    #[tokio::test]
+    async fn cluster_first_falls_back_without_cluster_dns() {
+        let resolv_conf = HostResolvConf::new("fallback");
+        let config = resolv_conf.config(&[]);
+        let dns_config = dns_config(&pod(serde_json::json!({})), &config)
+            .await
+            .unwrap();
+        assert_eq!(dns_config.servers, vec!["192.168.0.1"]);
+    }
+
+    #[tokio::test]
+    async fn none_policy_uses_only_dns_config() {
+        let resolv_conf = HostResolvConf::new("none");
+        let config = resolv_conf.config(&["10.96.0.10"]);
+        let dns_config = dns_config(
+            &pod(serde_json::json!({
+                "dnsPolicy": "None",
+                "dnsConfig": {
+                    "nameservers": ["1.1.1.1"],
+                    "searches": ["svc.example"],
+                    "options": [{ "name": "ndots", "value": "2" }, { "name": "rotate" }],
+                },
+            })),
+            &config,
+        )
+        .await
+        .unwrap();
+        assert_eq!(dns_config.servers, vec!["1.1.1.1"]);
+        assert_eq!(dns_config.searches, vec!["svc.example"]);
+        assert_eq!(dns_config.options, vec!["ndots:2", "rotate"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_policies() {
+        let resolv_conf = HostResolvConf::new("invalid");
+        let config = resolv_conf.config(&["10.96.0.10"]);
+        let none = pod(serde_json::json!({ "dnsPolicy": "None" }));
+        assert!(dns_config(&none, &config).await.is_err());
+        let no_servers = pod(serde_json::json!({ "dnsPolicy": "None", "dnsConfig": {} }));
+        assert!(dns_config(&no_servers, &config).await.is_err());
+        let unknown = pod(serde_json::json!({ "dnsPolicy": "Other" }));
+        assert!(dns_config(&unknown, &config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn missing_resolv_conf_is_empty() {
+        let config = Config {
+            resolv_conf: "/nonexistent/resolv.conf".to_string(),
+            ..crate::testing::config()
+        };
+        let dns_config = dns_config(&pod(serde_json::json!({ "dnsPolicy": "Default" })), &config)
+            .await
+            .unwrap();
+        assert_eq!(dns_config, cri::DnsConfig::default());
+    }
+
+    #[test]
+    fn merges_options_by_name() {
+        let mut dns_config = cri::DnsConfig {
+            servers: vec!["10.96.0.10".to_string()],
+            searches: vec![],
+            options: vec!["ndots:5".to_string()],
+        };
+        let pod_dns_config: PodDNSConfig = serde_json::from_value(serde_json::json!({
+            "nameservers": ["10.96.0.10", "1.1.1.1"],
+            "options": [{ "name": "ndots", "value": "1" }],
+        }))
+        .unwrap();
+        merge(&mut dns_config, &pod_dns_config);
+        assert_eq!(dns_config.servers, vec!["10.96.0.10", "1.1.1.1"]);
+        assert_eq!(dns_config.options, vec!["ndots:1"]);
+    }
+
+    #[test]
+    fn fits_resolver_limits() {
+        let mut dns_config = cri::DnsConfig {
+            servers: (1..=5).map(|i| format!("10.0.0.{}", i)).collect(),
+            searches: (1..=8)
+                .map(|i| format!("{}.{}", i, "a".repeat(60)))
+                .collect(),
+            options: vec![],
+        };
+        fit_limits(&mut dns_config);
+        assert_eq!(dns_config.servers.len(), MAX_NAMESERVERS);
+        assert!(dns_config.searches.len() <= MAX_SEARCH_PATHS);
+        assert!(dns_config.searches.join(" ").len() <= MAX_SEARCH_LIST_CHARS);
+    }
+}
@@ -0,0 +1,245 @@
+use k8s_openapi::api::core::v1::{Pod as KubePod, ResourceFieldSelector};
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use std::collections::BTreeMap;
+
+use crate::node::capacity::Resources;
+use crate::quantity;
+
+/// Facts about a running pod which the Downward API exposes to its containers.
+pub struct Context<'a> {
+    pub pod: &'a KubePod,
+    pub node_name: &'a str,
+    /// Sandbox IPs, primary first.
+    pub pod_ips: &'a [String],
+    pub host_ip: &'a str,
+    /// Node allocatable, which stands in for limits a container does not set.
+    pub allocatable: &'a Resources,
+}
+
+/// Returns the value of a `fieldRef`, such as `metadata.name` or `metadata.labels['app']`.
+pub fn field_value(context: &Context, field_path: &str) -> anyhow::Result<String> {
+    let metadata = &context.pod.metadata;
+    let spec = context.pod.spec.clone().unwrap_or_default();
+    if let Some((map, key)) = subscript(field_path) {
+        let values = match map {
+            "metadata.labels" => metadata.labels.as_ref(),
+            "metadata.annotations" => metadata.annotations.as_ref(),
+            _ => anyhow::bail!("Unsupported fieldRef {}.", field_path),
+        };
+        return Ok(values
+            .and_then(|values| values.get(key))
+            .cloned()
+            .unwrap_or_default());
+    }
+    let value = match field_path {
+        "metadata.name" => metadata.name.clone().unwrap_or_default(),
+        "metadata.namespace" => metadata.namespace.clone().unwrap_or_default(),
+        "metadata.uid" => metadata.uid.clone().unwrap_or_default(),
+        "metadata.labels" => format_map(metadata.labels.as_ref()),
+        "metadata.annotations" => format_map(metadata.annotations.as_ref()),
+        "spec.nodeName" => spec
+            .node_name
+            .unwrap_or_else(|| context.node_name.to_string()),
+        "spec.serviceAccountName" => spec.service_account_name.unwrap_or_default(),
+        "status.podIP" => context.pod_ips.first().cloned().unwrap_or_default(),
+        "status.podIPs" => context.pod_ips.join(","),
+        "status.hostIP" => context.host_ip.to_string(),
+        _ => anyhow::bail!("Unsupported fieldRef {}.", field_path),
+    };
+    Ok(value)
+}
+
+/// Splits `metadata.labels['key']` into the map and key.
+fn subscript(field_path: &str) -> Option<(&str, &str)> {
+    let open = field_path.find("['")?;
+    if !field_path.ends_with("']") || open + 2 > field_path.len() - 2 {
+        return None;
+    }
+    Some((
+        &field_path[..open],
+        &field_path[open + 2..field_path.len() - 2],
+    ))
+}
+
+/// Formats labels or annotations as `key="value"` lines, sorted by key.
+fn format_map(values: Option<&BTreeMap<String, String>>) -> String {
+    values
+        .iter()
+        .flat_map(|values| values.iter())
+        .map(|(key, value)| format!("{}={:?}", key, value))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Returns the value of a `resourceFieldRef`, such as `limits.memory`, in units of its divisor
+/// and rounded up.
+pub fn resource_value(
+    context: &Context,
+    container_name: &str,
+    selector: &ResourceFieldSelector,
+) -> anyhow::Result<String> {
+    let container_name = selector
+        .container_name
+        .as_deref()
+        .filter(|name| !name.is_empty())
+        .unwrap_or(container_name);
+    let container = context
+        .pod
+        .spec
+        .as_ref()
+        .and_then(|spec| {
+            spec.containers
+                .iter()
+                .find(|container| container.name == container_name)
+        })
+        .ok_or_else(|| anyhow::anyhow!("Container {} not found.", container_name))?;
+    let resources = container.resources.clone().unwrap_or_default();
+
+    let mut parts = selector.resource.splitn(2, '.');
+    let (list, name) = match (parts.next(), parts.next()) {
+        (Some("limits"), Some(name)) => (resources.limits.as_ref(), name),
+        (Some("requests"), Some(name)) => (resources.requests.as_ref(), name),
+        _ => anyhow::bail!("Unsupported resourceFieldRef {}.", &selector.resource),
+    };
+    let millis = match list.and_then(|list| list.get(name)) {
+        Some(value) => quantity::milli_value(value)?,
+        // Containers without a limit may use all of the node's allocatable resources.
+        None if selector.resource.starts_with("limits.") => {
+            context.allocatable.get(name).cloned().unwrap_or(0)
+        }
+        None => 0,
+    };
+
+    let divisor = selector
+        .divisor
+        .clone()
+        .unwrap_or_else(|| Quantity("1".to_string()));
+    let value = if name == "cpu" {
+        div_ceil(millis, quantity::milli_value(&divisor)?)
+    } else {
+        let whole = div_ceil(millis, 1000);
+        div_ceil(whole, quantity::value(&divisor)?)
+    };
+    Ok(value.to_string())
+}
+
+fn div_ceil(value: i128, divisor: i128) -> i128 {
+    if divisor <= 0 {
+        return value;
+    }
+    (value + divisor - 1) / divisor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pod() -> KubePod {
+        let mut pod = crate::testing::kube_pod(serde_json::json!({
+            "serviceAccountName": "web",
+            "containers": [
+                {
+                    "name": "app",
+                    "resources": {
+                        "requests": { "cpu": "250m", "memory": "64Mi" },
+                        "limits": { "memory": "128Mi" },
+                    },
+                },
+                { "name": "sidecar" },
+            ],
+        }));
+        pod.metadata.labels = Some(
+            vec![("app", "web"), ("tier", "front")]
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        );
+        pod
+    }
+
+    fn with_context<T>(f: impl FnOnce(&Context) -> T) -> T {
+        let pod = pod();
+        let pod_ips = vec!["10.244.0.5".to_string(), "fd00::5".to_string()];
+        let allocatable: Resources = vec![
+            ("cpu".to_string(), 3500),
+            ("memory".to_string(), 4 * 1024 * 1024 * 1024 * 1000),
+        ]
+        .into_iter()
+        .collect();
+        f(&Context {
+            pod: &pod,
+            node_name: "node-1",
+            pod_ips: &pod_ips,
+            host_ip: "192.168.0.10",
+            allocatable: &allocatable,
+        })
+    }
+
+    fn selector(resource: &str, divisor: Option<&str>) -> ResourceFieldSelector {
+        ResourceFieldSelector {
+            container_name: None,
+            divisor: divisor.map(|divisor| Quantity(divisor.to_string())),
+            resource: resource.to_string(),
+        }
+    }
+
+    #[test]
+    fn resolves_field_refs() {
+        with_context(|context| {
+            let field = |path| field_value(context, path).unwrap();
+            assert_eq!(field("metadata.name"), "web-0");
+            assert_eq!(field("metadata.namespace"), "ns");
+            assert_eq!(field("metadata.uid"), "1234");
+            assert_eq!(field("metadata.labels['app']"), "web");
+            assert_eq!(field("metadata.labels['missing']"), "");
+            assert_eq!(field("metadata.labels"), "app=\"web\"\ntier=\"front\"");
+            assert_eq!(field("spec.nodeName"), "node-1");
+            assert_eq!(field("spec.serviceAccountName"), "web");
+            assert_eq!(field("status.podIP"), "10.244.0.5");
+            assert_eq!(field("status.podIPs"), "10.244.0.5,fd00::5");
+            assert_eq!(field("status.hostIP"), "192.168.0.10");
+        });
+    }
+
+    #[test]
+    fn rejects_unsupported_field_refs() {
+        with_context(|context| {
+            assert!(field_value(context, "spec.hostname").is_err());
+            assert!(field_value(context, "spec.labels['app']").is_err());
+        });
+    }
+
+    #[test]
+    fn resolves_resource_field_refs() {
+        with_context(|context| {
+            let resource = |resource, divisor| {
+                resource_value(context, "app", &selector(resource, divisor)).unwrap()
+            };
+            // CPU rounds up to whole cores unless a smaller divisor is given.
+            assert_eq!(resource("requests.cpu", None), "1");
+            assert_eq!(resource("requests.cpu", Some("1m")), "250");
+            assert_eq!(resource("requests.memory", None), "67108864");
+            assert_eq!(resource("limits.memory", Some("1Mi")), "128");
+            // Unset limits fall back to node allocatable.
+            assert_eq!(resource("limits.cpu", Some("1m")), "3500");
+            assert_eq!(resource("requests.ephemeral-storage", None), "0");
+        });
+    }
+
+    #[test]
+    fn resolves_resource_field_refs_of_other_containers() {
+        with_context(|context| {
+            let other = ResourceFieldSelector {
+                container_name: Some("app".to_string()),
+                ..selector("requests.memory", Some("1Mi"))
+            };
+            assert_eq!(resource_value(context, "sidecar", &other).unwrap(), "64");
+            let missing = ResourceFieldSelector {
+                container_name: Some("missing".to_string()),
+                ..other.clone()
+            };
+            assert!(resource_value(context, "app", &missing).is_err());
+            assert!(resource_value(context, "app", &selector("cpu", None)).is_err());
+        });
+    }
+}
@@ -0,0 +1,575 @@
+use k8s_cri::v1alpha2 as cri;
+use k8s_openapi::api::core::v1::{
+    ConfigMap, ConfigMapKeySelector, ObjectFieldSelector, Pod as KubePod, ResourceFieldSelector,
+    Secret, SecretKeySelector,
+};
+use log::debug;
+use std::collections::HashMap;
+
+use super::{downward, events, expansion};
+
+/// The value of an environment variable. Downward API values are resolved once the sandbox is
+/// running, as they may refer to its IPs.
+#[derive(Debug)]
+pub enum Value {
+    /// A value given in the pod spec, which may refer to earlier variables as `$(VAR)`.
+    Literal(String),
+    Resolved(String),
+    Field(ObjectFieldSelector),
+    ResourceField(ResourceFieldSelector),
+}
+
+/// A container's environment variables, in the order they are declared. A later definition of a
+/// name overrides an earlier one.
+pub type Env = Vec<(String, Value)>;
+
+/// A container's configuration refers to a ConfigMap or Secret, or a key within one, which
+/// does not exist. The container cannot be created until it does.
+#[derive(Debug)]
+pub struct ContainerConfigError(pub String);
+
+impl std::fmt::Display for ContainerConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", &self.0)
+    }
+}
+
+impl std::error::Error for ContainerConfigError {}
+
+/// ConfigMaps and Secrets in the pod's namespace, fetched once each and cached. `None` marks
+/// objects which do not exist.
+struct Sources {
+    client: kube::Client,
+    namespace: String,
+    config_maps: HashMap<String, Option<ConfigMap>>,
+    secrets: HashMap<String, Option<Secret>>,
+}
+
+impl Sources {
+    async fn config_map(&mut self, name: &str) -> anyhow::Result<Option<&ConfigMap>> {
+        if !self.config_maps.contains_key(name) {
+            let api: kube::Api<ConfigMap> =
+                kube::Api::namespaced(self.client.clone(), &self.namespace);
+            let config_map = get_optional(api.get(name).await)?;
+            self.config_maps.insert(name.to_string(), config_map);
+        }
+        Ok(self.config_maps[name].as_ref())
+    }
+
+    async fn secret(&mut self, name: &str) -> anyhow::Result<Option<&Secret>> {
+        if !self.secrets.contains_key(name) {
+            let api: kube::Api<Secret> =
+                kube::Api::namespaced(self.client.clone(), &self.namespace);
+            let secret = get_optional(api.get(name).await)?;
+            self.secrets.insert(name.to_string(), secret);
+        }
+        Ok(self.secrets[name].as_ref())
+    }
+
+    /// Returns the value of a `configMapKeyRef`, or `None` if it is optional and missing.
+    async fn config_map_key(
+        &mut self,
+        selector: &ConfigMapKeySelector,
+    ) -> anyhow::Result<Option<String>> {
+        let name = selector.name.as_deref().unwrap_or_default();
+        let optional = selector.optional.unwrap_or(false);
+        let value = match self.config_map(name).await? {
+            Some(config_map) => config_map
+                .data
+                .as_ref()
+                .and_then(|data| data.get(&selector.key))
+                .cloned(),
+            None if optional => return Ok(None),
+            None => anyhow::bail!(ContainerConfigError(format!(
+                "configmap \"{}\" not found",
+                name
+            ))),
+        };
+        match value {
+            Some(value) => Ok(Some(value)),
+            None if optional => Ok(None),
+            None => anyhow::bail!(ContainerConfigError(format!(
+                "couldn't find key {} in ConfigMap {}/{}",
+                &selector.key, &self.namespace, name
+            ))),
+        }
+    }
+
+    /// Returns the value of a `secretKeyRef`, or `None` if it is optional and missing.
+    async fn secret_key(&mut self, selector: &SecretKeySelector) -> anyhow::Result<Option<String>> {
+        let name = selector.name.as_deref().unwrap_or_default();
+        let optional = selector.optional.unwrap_or(false);
+        let value = match self.secret(name).await? {
+            Some(secret) => secret
+                .data
+                .as_ref()
+                .and_then(|data| data.get(&selector.key))
+                .map(|value| String::from_utf8_lossy(&value.0).to_string()),
+            None if optional => return Ok(None),
+            None => anyhow::bail!(ContainerConfigError(format!(
+                "secret \"{}\" not found",
+                name
+            ))),
+        };
+        match value {
+            Some(value) => Ok(Some(value)),
+            None if optional => Ok(None),
+            None => anyhow::bail!(ContainerConfigError(format!(
+                "couldn't find key {} in Secret {}/{}",
+                &selector.key, &self.namespace, name
+            ))),
+        }
+    }
+}
+
+fn get_optional<T>(result: Result<T, kube::Error>) -> anyhow::Result<Option<T>> {
+    match result {
+        Ok(object) => Ok(Some(object)),
+        Err(kube::Error::Api(e)) if e.code == 404 => Ok(None),
+        Err(e) => anyhow::bail!(e),
+    }
+}
+
+/// Resolves the environment of each container in a pod, keyed by container name.
+///
+/// Values from `envFrom`, `configMapKeyRef` and `secretKeyRef` are read through the API server.
+/// A missing ConfigMap, Secret or key fails with `ContainerConfigError` unless the reference is
+/// optional, in which case the variables are left unset.
+pub async fn resolve(
+    client: kube::Client,
+    pod: &KubePod,
+    node_name: &str,
+) -> anyhow::Result<HashMap<String, Env>> {
+    let mut sources = Sources {
+        client,
+        namespace: pod
+            .metadata
+            .namespace
+            .clone()
+            .unwrap_or_else(|| "default".to_string()),
+        config_maps: HashMap::new(),
+        secrets: HashMap::new(),
+    };
+    let mut envs = HashMap::new();
+    let containers = pod
+        .spec
+        .as_ref()
+        .map(|spec| spec.containers.as_slice())
+        .unwrap_or_default();
+    for container in containers {
+        let mut env = vec![];
+        for source in container.env_from.iter().flatten() {
+            let prefix = source.prefix.as_deref().unwrap_or_default();
+            let (kind, name, data) = if let Some(reference) = &source.config_map_ref {
+                let name = reference.name.as_deref().unwrap_or_default();
+                let data = match sources.config_map(name).await? {
+                    Some(config_map) => config_map.data.clone().unwrap_or_default(),
+                    None if reference.optional.unwrap_or(false) => continue,
+                    None => anyhow::bail!(ContainerConfigError(format!(
+                        "configmap \"{}\" not found",
+                        name
+                    ))),
+                };
+                ("configMap", name, data)
+            } else if let Some(reference) = &source.secret_ref {
+                let name = reference.name.as_deref().unwrap_or_default();
+                let data = match sources.secret(name).await? {
+                    Some(secret) => secret
+                        .data
+                        .iter()
+                        .flatten()
+                        .map(|(key, value)| {
+                            (key.clone(), String::from_utf8_lossy(&value.0).to_string())
+                        })
+                        .collect(),
+                    None if reference.optional.unwrap_or(false) => continue,
+                    None => anyhow::bail!(ContainerConfigError(format!(
+                        "secret \"{}\" not found",
+                        name
+                    ))),
+                };
+                ("secret", name, data)
+            } else {
+                continue;
+            };
+
+            let mut invalid_keys = vec![];
+            for (key, value) in data {
+                let key = format!("{}{}", prefix, key);
+                if is_env_var_name(&key) {
+                    env.push((key, Value::Resolved(value)));
+                } else {
+                    invalid_keys.push(key);
+                }
+            }
+            if !invalid_keys.is_empty() {
+                let message = format!(
+                    "Keys [{}] from the EnvFrom {} {}/{} were skipped since they are considered invalid environment variable names.",
+                    invalid_keys.join(", "),
+                    kind,
+                    &sources.namespace,
+                    name
+                );
+                events::warn(
+                    sources.client.clone(),
+                    pod,
+                    node_name,
+                    "InvalidEnvironmentVariableNames",
+                    &message,
+                )
+                .await;
+            }
+        }
+        // Variables from env override those from envFrom.
+        for var in container.env.iter().flatten() {
+            let value = match &var.value_from {
+                Some(source) => {
+                    if let Some(selector) = &source.config_map_key_ref {
+                        sources.config_map_key(selector).await?.map(Value::Resolved)
+                    } else if let Some(selector) = &source.secret_key_ref {
+                        sources.secret_key(selector).await?.map(Value::Resolved)
+                    } else if let Some(selector) = &source.field_ref {
+                        Some(Value::Field(selector.clone()))
+                    } else {
+                        source.resource_field_ref.clone().map(Value::ResourceField)
+                    }
+                }
+                None => Some(Value::Literal(var.value.clone().unwrap_or_default())),
+            };
+            match value {
+                Some(value) => env.push((var.name.clone(), value)),
+                None => debug!(
+                    "Leaving optional variable {} of container {} unset.",
+                    &var.name, &container.name
+                ),
+            }
+        }
+        envs.insert(container.name.clone(), env);
+    }
+    Ok(envs)
+}
+
+/// Whether a name is a valid environment variable name, matching `[-._a-zA-Z][-._a-zA-Z0-9]*`.
+fn is_env_var_name(name: &str) -> bool {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_';
+    match name.chars().next() {
+        Some(first) => !first.is_ascii_digit() && name.chars().all(valid),
+        None => false,
+    }
+}
+
+/// Resolves the Downward API values of a container's environment and expands the `$(VAR)`
+/// references in its literal values. A reference sees only the variables defined before it.
+pub fn finish(
+    env: Env,
+    container_name: &str,
+    context: &downward::Context,
+) -> anyhow::Result<Vec<cri::KeyValue>> {
+    let mut names = vec![];
+    let mut values: HashMap<String, String> = HashMap::new();
+    for (name, value) in env {
+        let value = match value {
+            Value::Literal(value) => {
+                expansion::expand(&value, |name| values.get(name).map(String::as_str))
+            }
+            Value::Resolved(value) => value,
+            Value::Field(selector) => downward::field_value(context, &selector.field_path)?,
+            Value::ResourceField(selector) => {
+                downward::resource_value(context, container_name, &selector)?
+            }
+        };
+        if values.insert(name.clone(), value).is_none() {
+            names.push(name);
+        }
+    }
+    Ok(names
+        .into_iter()
+        .map(|key| {
+            let value = values.remove(&key).unwrap_or_default();
+            cri::KeyValue { key, value }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sources with every object already cached, so no API server is contacted.
+    fn sources() -> Sources {
+        let config_map: ConfigMap = serde_json::from_value(serde_json::json!({
+            "metadata": { "name": "settings" },
+            "data": { "level": "debug" },
+        }))
+        .unwrap();
+        let secret: Secret = serde_json::from_value(serde_json::json!({
+            "metadata": { "name": "credentials" },
+            // "hunter2"
+            "data": { "password": "aHVudGVyMg==" },
+        }))
+        .unwrap();
+        Sources {
+            client: kube::Client::new(kube::Config::new("http://127.0.0.1:1".parse().unwrap())),
+            namespace: "ns".to_string(),
+            config_maps: vec![
+                ("settings".to_string(), Some(config_map)),
+                ("missing".to_string(), None),
+            ]
+            .into_iter()
+            .collect(),
+            secrets: vec![
+                ("credentials".to_string(), Some(secret)),
+                ("missing".to_string(), None),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    fn config_map_key(name: &str, key: &str, optional: bool) -> ConfigMapKeySelector {
+        ConfigMapKeySelector {
+            name: Some(name.to_string()),
+            key: key.to_string(),
+            optional: Some(optional),
+        }
+    }
+
+    fn secret_key(name: &str, key: &str, optional: bool) -> SecretKeySelector {
+        SecretKeySelector {
+            name: Some(name.to_string()),
+            key: key.to_string(),
+            optional: Some(optional),
+        }
+    }
+
+    fn is_config_error<T: std::fmt::Debug>(result: anyhow::Result<T>) -> bool {
+        result
+            .unwrap_err()
+            .downcast_ref::<ContainerConfigError>()
+            .is_some()
+    }
+
+    #[tokio::test]
+    async fn resolves_config_map_keys() {
+        let mut sources = sources();
+        let value = sources
+            .config_map_key(&config_map_key("settings", "level", false))
+            .await
+            .unwrap();
+        assert_eq!(value.as_deref(), Some("debug"));
+        let missing_key = sources
+            .config_map_key(&config_map_key("settings", "other", false))
+            .await;
+        assert!(is_config_error(missing_key));
+        let missing = sources
+            .config_map_key(&config_map_key("missing", "level", false))
+            .await;
+        assert!(is_config_error(missing));
+    }
+
+    #[tokio::test]
+    async fn resolves_secret_keys() {
+        let mut sources = sources();
+        let value = sources
+            .secret_key(&secret_key("credentials", "password", false))
+            .await
+            .unwrap();
+        assert_eq!(value.as_deref(), Some("hunter2"));
+        let missing = sources
+            .secret_key(&secret_key("missing", "password", false))
+            .await;
+        assert!(is_config_error(missing));
+    }
+
+    #[tokio::test]
+    async fn skips_optional_references() {
+        let mut sources = sources();
+        for (name, key) in &[("settings", "other"), ("missing", "level")] {
+            let value = sources
+                .config_map_key(&config_map_key(name, key, true))
+                .await
+                .unwrap();
+            assert_eq!(value, None);
+        }
+        let value = sources
+            .secret_key(&secret_key("credentials", "other", true))
+            .await
+            .unwrap();
+        assert_eq!(value, None);
+    }
+
+    fn api_routes() -> Vec<(&'static str, u16, serde_json::Value)> {
+        vec![
+            (
+                "/api/v1/namespaces/ns/configmaps/settings",
+                200,
+                serde_json::json!({
+                    "apiVersion": "v1",
+                    "kind": "ConfigMap",
+                    "metadata": { "name": "settings" },
+                    "data": { "level": "debug", "1st": "x", "mode": "fast" },
+                }),
+            ),
+            (
+                "/api/v1/namespaces/ns/secrets/credentials",
+                200,
+                serde_json::json!({
+                    "apiVersion": "v1",
+                    "kind": "Secret",
+                    "metadata": { "name": "credentials" },
+                    "data": { "password": "aHVudGVyMg==" },
+                }),
+            ),
+            (
+                "/api/v1/namespaces/ns/events",
+                201,
+                serde_json::json!({
+                    "apiVersion": "v1",
+                    "kind": "Event",
+                    "metadata": { "name": "web-0.1" },
+                    "involvedObject": {},
+                }),
+            ),
+        ]
+    }
+
+    /// The resolved variables of the pod's `app` container.
+    fn resolved(mut envs: HashMap<String, Env>) -> Vec<(String, String)> {
+        envs.remove("app")
+            .unwrap()
+            .into_iter()
+            .map(|(key, value)| match value {
+                Value::Literal(value) | Value::Resolved(value) => (key, value),
+                _ => panic!("{} is not resolved", key),
+            })
+            .collect()
+    }
+
+    fn pair(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[tokio::test]
+    async fn expands_env_from_with_a_prefix() {
+        let (client, _) = crate::testing::recording_api_server(api_routes());
+        let pod = crate::testing::kube_pod(serde_json::json!({
+            "containers": [{
+                "name": "app",
+                "envFrom": [
+                    { "prefix": "APP_", "configMapRef": { "name": "settings" } },
+                    { "secretRef": { "name": "credentials" } },
+                ],
+            }],
+        }));
+        let mut env = resolved(resolve(client, &pod, "node-1").await.unwrap());
+        env.sort();
+        assert_eq!(
+            env,
+            vec![
+                pair("APP_1st", "x"),
+                pair("APP_level", "debug"),
+                pair("APP_mode", "fast"),
+                pair("password", "hunter2"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_invalid_keys_with_a_warning() {
+        let (client, requests) = crate::testing::recording_api_server(api_routes());
+        let pod = crate::testing::kube_pod(serde_json::json!({
+            "containers": [{
+                "name": "app",
+                "envFrom": [{ "configMapRef": { "name": "settings" } }],
+            }],
+        }));
+        let mut env = resolved(resolve(client, &pod, "node-1").await.unwrap());
+        env.sort();
+        assert_eq!(env, vec![pair("level", "debug"), pair("mode", "fast")]);
+
+        let requests = requests.lock().unwrap();
+        let events: Vec<_> = requests
+            .iter()
+            .filter(|(method, path, _)| {
+                method == hyper::Method::POST && path == "/api/v1/namespaces/ns/events"
+            })
+            .map(|(_, _, event)| event)
+            .collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["type"], "Warning");
+        assert_eq!(events[0]["reason"], "InvalidEnvironmentVariableNames");
+        assert_eq!(events[0]["involvedObject"]["uid"], "1234");
+        assert_eq!(events[0]["source"]["host"], "node-1");
+        assert_eq!(
+            events[0]["message"],
+            "Keys [1st] from the EnvFrom configMap ns/settings were skipped since they are considered invalid environment variable names."
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_optional_env_from_sources() {
+        let (client, _) = crate::testing::recording_api_server(api_routes());
+        let pod = crate::testing::kube_pod(serde_json::json!({
+            "containers": [{
+                "name": "app",
+                "envFrom": [
+                    { "configMapRef": { "name": "missing", "optional": true } },
+                    { "secretRef": { "name": "missing", "optional": true } },
+                ],
+            }],
+        }));
+        assert!(resolved(resolve(client, &pod, "node-1").await.unwrap()).is_empty());
+
+        for source in &["configMapRef", "secretRef"] {
+            let (client, _) = crate::testing::recording_api_server(api_routes());
+            let pod = crate::testing::kube_pod(serde_json::json!({
+                "containers": [{
+                    "name": "app",
+                    "envFrom": [{ *source: { "name": "missing" } }],
+                }],
+            }));
+            assert!(is_config_error(resolve(client, &pod, "node-1").await));
+        }
+    }
+
+    #[tokio::test]
+    async fn env_overrides_env_from() {
+        let (client, _) = crate::testing::recording_api_server(api_routes());
+        let pod = crate::testing::kube_pod(serde_json::json!({
+            "containers": [{
+                "name": "app",
+                "envFrom": [{ "configMapRef": { "name": "settings" } }],
+                "env": [
+                    { "name": "level", "value": "info" },
+                    { "name": "mode", "value": "slow" },
+                    { "name": "mode", "value": "safe" },
+                ],
+            }],
+        }));
+        // Later definitions override earlier ones.
+        let env: HashMap<_, _> = resolved(resolve(client, &pod, "node-1").await.unwrap())
+            .into_iter()
+            .collect();
+        assert_eq!(env.len(), 2);
+        assert_eq!(env["level"], "info");
+        assert_eq!(env["mode"], "safe");
+    }
+
+    #[test]
+    fn treats_not_found_as_missing() {
+        let not_found = kube::Error::Api(kube::error::ErrorResponse {
+            status: "Failure".to_string(),
+            message: "not found".to_string(),
+            reason: "NotFound".to_string(),
+            code: 404,
+        });
+        assert!(get_optional::<ConfigMap>(Err(not_found)).unwrap().is_none());
+        let forbidden = kube::Error::Api(kube::error::ErrorResponse {
+            status: "Failure".to_string(),
+            message: "forbidden".to_string(),
+            reason: "Forbidden".to_string(),
+            code: 403,
+        });
+        assert!(get_optional::<ConfigMap>(Err(forbidden)).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use k8s_openapi::api::core::v1::{Event, EventSource, ObjectReference, Pod as KubePod};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use kube::api::PostParams;
+use log::{error, warn};
+
+const COMPONENT: &str = "kubelet";
+
+/// Records a Warning event against a pod. Failures are logged rather than returned, as events
+/// are informational.
+pub async fn warn(
+    client: kube::Client,
+    pod: &KubePod,
+    node_name: &str,
+    reason: &str,
+    message: &str,
+) {
+    warn!("{}: {}", reason, message);
+    let namespace = pod
+        .metadata
+        .namespace
+        .clone()
+        .unwrap_or_else(|| "default".to_string());
+    let pod_name = pod.metadata.name.clone().unwrap_or_default();
+    let now = chrono::Utc::now();
+    // Nanoseconds overflow after 2262, seconds are still unique enough alongside the pod name.
+    let suffix = now.timestamp_nanos_opt().unwrap_or_else(|| now.timestamp());
+    let event = Event {
+        metadata: ObjectMeta {
+            // Event names only need to be unique, this matches the format used by client-go.
+            name: Some(format!("{}.{:x}", &pod_name, suffix)),
+            namespace: Some(namespace.clone()),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some("v1".to_string()),
+            kind: Some("Pod".to_string()),
+            name: Some(pod_name),
+            namespace: Some(namespace.clone()),
+            uid: pod.metadata.uid.clone(),
+            resource_version: pod.metadata.resource_version.clone(),
+            ..Default::default()
+        },
+        reason: Some(reason.to_string()),
+        message: Some(message.to_string()),
+        type_: Some("Warning".to_string()),
+        source: Some(EventSource {
+            component: Some(COMPONENT.to_string()),
+            host: Some(node_name.to_string()),
+        }),
+        first_timestamp: Some(Time(now)),
+        last_timestamp: Some(Time(now)),
+        count: Some(1),
+        ..Default::default()
+    };
+    let events: kube::Api<Event> = kube::Api::namespaced(client, &namespace);
+    if let Err(e) = events.create(&PostParams::default(), &event).await {
+        error!("Error recording event {}: {:?}", reason, &e);
+    }
+}
@@ -0,0 +1,239 @@
+//! Expansion of `$(VAR)` references, following the Kubernetes rules.
+//!
+//! References to undefined variables are left as they are, and `$$` escapes an operator, so
+//! `$$(VAR)` becomes the literal `$(VAR)`. Any other use of `$` passes through unchanged.
+
+const OPERATOR: u8 = b'$';
+const REFERENCE_OPENER: u8 = b'(';
+const REFERENCE_CLOSER: u8 = b')';
+
+/// Expands the variable references in `input`, looking each name up with `lookup`.
+pub fn expand<'a, F>(input: &str, lookup: F) -> String
+where
+    F: Fn(&str) -> Option<&'a str>,
+{
+    let bytes = input.as_bytes();
+    let mut output = String::with_capacity(input.len());
+    let mut checkpoint = 0;
+    let mut cursor = 0;
+    while cursor < bytes.len() {
+        if bytes[cursor] == OPERATOR && cursor + 1 < bytes.len() {
+            output.push_str(&input[checkpoint..cursor]);
+            let rest = &input[cursor + 1..];
+            let advance = match rest.as_bytes()[0] {
+                // An escaped operator.
+                OPERATOR => {
+                    output.push('$');
+                    1
+                }
+                REFERENCE_OPENER => match rest.bytes().position(|b| b == REFERENCE_CLOSER) {
+                    Some(end) => {
+                        let name = &rest[1..end];
+                        match lookup(name) {
+                            Some(value) => output.push_str(value),
+                            None => output.push_str(&format!("$({})", name)),
+                        }
+                        end + 1
+                    }
+                    // An unterminated reference is copied, and the text after the opener is
+                    // still scanned for references.
+                    None => {
+                        output.push_str("$(");
+                        1
+                    }
+                },
+                // A bare operator, with the character after it copied as ordinary text.
+                _ => {
+                    output.push('$');
+                    0
+                }
+            };
+            cursor += advance;
+            checkpoint = cursor + 1;
+        }
+        cursor += 1;
+    }
+    output.push_str(&input[checkpoint..]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn expands_references_to_earlier_variables() {
+        let env = [("FOO", "bar"), ("ZOO", "$(FOO)-1"), ("BLU", "$(ZOO)-2")];
+        let mut declared = map(&env);
+        for (name, value) in &env {
+            let expanded = expand(value, |name| declared.get(name).map(String::as_str));
+            declared.insert(name.to_string(), expanded);
+        }
+        assert_eq!(
+            declared,
+            map(&[("FOO", "bar"), ("ZOO", "bar-1"), ("BLU", "bar-1-2")])
+        );
+    }
+
+    #[test]
+    fn expands_mapping_vectors() {
+        let context = map(&[
+            ("VAR_A", "A"),
+            ("VAR_B", "B"),
+            ("VAR_C", "C"),
+            ("VAR_REF", "$(VAR_A)"),
+            ("VAR_EMPTY", ""),
+        ]);
+        let cases = [
+            ("whole string", "$(VAR_A)", "A"),
+            ("repeat", "$(VAR_A)-$(VAR_A)", "A-A"),
+            ("beginning", "$(VAR_A)-1", "A-1"),
+            ("middle", "___$(VAR_B)___", "___B___"),
+            ("end", "___$(VAR_C)", "___C"),
+            ("compound", "$(VAR_A)_$(VAR_B)_$(VAR_C)", "A_B_C"),
+            ("escape & expand", "$$(VAR_B)_$(VAR_A)", "$(VAR_B)_A"),
+            (
+                "compound escape",
+                "$$(VAR_A)_$$(VAR_B)",
+                "$(VAR_A)_$(VAR_B)",
+            ),
+            ("mixed in escapes", "f000-$$VAR_A", "f000-$VAR_A"),
+            ("backslash escape ignored", "foo\\$(VAR_C)bar", "foo\\Cbar"),
+            (
+                "backslash escape ignored",
+                "foo\\\\$(VAR_C)bar",
+                "foo\\\\Cbar",
+            ),
+            (
+                "lots of backslashes",
+                "foo\\\\\\\\$(VAR_A)bar",
+                "foo\\\\\\\\Abar",
+            ),
+            (
+                "nested var references",
+                "$(VAR_A$(VAR_B))",
+                "$(VAR_A$(VAR_B))",
+            ),
+            (
+                "nested var references second type",
+                "$(VAR_A$(VAR_B)",
+                "$(VAR_A$(VAR_B)",
+            ),
+            ("value is a reference", "$(VAR_REF)", "$(VAR_A)"),
+            (
+                "value is a reference x 2",
+                "%%$(VAR_REF)--$(VAR_REF)%%",
+                "%%$(VAR_A)--$(VAR_A)%%",
+            ),
+            ("empty var", "foo$(VAR_EMPTY)bar", "foobar"),
+            (
+                "unterminated expression",
+                "foo$(VAR_Awhoops!",
+                "foo$(VAR_Awhoops!",
+            ),
+            (
+                "expression without operator",
+                "f00__(VAR_A)__",
+                "f00__(VAR_A)__",
+            ),
+            ("shell special vars pass through", "$?_boo_$!", "$?_boo_$!"),
+            ("bare operators are ignored", "$VAR_A", "$VAR_A"),
+            (
+                "undefined vars are passed through",
+                "$(VAR_DNE)",
+                "$(VAR_DNE)",
+            ),
+            (
+                "multiple (even) operators, var undefined",
+                "$$$$$$(BIG_MONEY)",
+                "$$$(BIG_MONEY)",
+            ),
+            (
+                "multiple (even) operators, var defined",
+                "$$$$$$(VAR_A)",
+                "$$$(VAR_A)",
+            ),
+            (
+                "multiple (odd) operators, var undefined",
+                "$$$$$$$(GOOD_ODDS)",
+                "$$$$(GOOD_ODDS)",
+            ),
+            (
+                "multiple (odd) operators, var defined",
+                "$$$$$$$(VAR_A)",
+                "$$$A",
+            ),
+            ("missing open expression", "$VAR_A)", "$VAR_A)"),
+            ("shell syntax ignored", "${VAR_A}", "${VAR_A}"),
+            (
+                "trailing incomplete expression not consumed",
+                "$(VAR_B)_______$(A",
+                "B_______$(A",
+            ),
+            (
+                "trailing incomplete expression, no content, is not consumed",
+                "$(VAR_C)_______$(",
+                "C_______$(",
+            ),
+            (
+                "operator at end of input string is preserved",
+                "$(VAR_A)foobarzab$",
+                "Afoobarzab$",
+            ),
+            (
+                "shell escaped incomplete expr",
+                "foo-\\$(VAR_A",
+                "foo-\\$(VAR_A",
+            ),
+            ("lots of $( in middle", "--$($($($($--", "--$($($($($--"),
+            (
+                "lots of $( in beginning",
+                "$($($($($--foo$(",
+                "$($($($($--foo$(",
+            ),
+            ("lots of $( at end", "foo0--$($($($(", "foo0--$($($($("),
+            (
+                "escaped operators in variable names are not escaped",
+                "$(foo$$var)",
+                "$(foo$$var)",
+            ),
+            ("newline not expanded", "\n", "\n"),
+        ];
+        for (name, input, expected) in &cases {
+            let expanded = expand(input, |name| context.get(name).map(String::as_str));
+            assert_eq!(&expanded, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn earlier_contexts_take_precedence() {
+        let first = map(&[("VAR_A", "A"), ("VAR_EMPTY", "")]);
+        let second = map(&[("VAR_A", "shadowed"), ("VAR_B", "B"), ("VAR_C", "C")]);
+        let lookup = |name: &str| {
+            first
+                .get(name)
+                .or_else(|| second.get(name))
+                .map(String::as_str)
+        };
+        assert_eq!(expand("$(VAR_A)_$(VAR_B)_$(VAR_C)", lookup), "A_B_C");
+        assert_eq!(expand("foo$(VAR_EMPTY)bar", lookup), "foobar");
+        assert_eq!(expand("$(VAR_DNE)", lookup), "$(VAR_DNE)");
+    }
+
+    #[test]
+    fn copies_multibyte_text() {
+        let context = map(&[("VAR_A", "ä")]);
+        let expanded = expand("ü$é$(VAR_A)ö$", |name| {
+            context.get(name).map(String::as_str)
+        });
+        assert_eq!(expanded, "ü$éäö$");
+    }
+}
@@ -0,0 +1,193 @@
+use k8s_cri::v1alpha2 as cri;
+use k8s_openapi::api::core::v1::{Container, Pod as KubePod};
+use std::path::{Path, PathBuf};
+
+use super::namespaces;
+
+const HOSTS_PATH: &str = "/etc/hosts";
+
+/// Returns where the managed hosts file of a pod is kept, under its pod directory.
+pub fn hosts_file_path(pods_dir: &Path, uid: &str) -> PathBuf {
+    pods_dir.join(uid).join("etc-hosts")
+}
+
+/// Returns the pod's fully qualified domain name, when `spec.subdomain` is set.
+fn fqdn(pod: &KubePod, cluster_domain: &str) -> Option<String> {
+    let subdomain = pod
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.subdomain.as_ref())
+        .filter(|subdomain| !subdomain.is_empty())?;
+    Some(format!(
+        "{}.{}.{}.svc.{}",
+        namespaces::hostname(pod),
+        subdomain,
+        pod.metadata.namespace.as_deref().unwrap_or("default"),
+        cluster_domain
+    ))
+}
+
+/// Renders the hosts file, mapping each pod IP to its hostname and FQDN, followed by
+/// `spec.hostAliases`.
+///
+/// `setHostnameAsFQDN` is not part of the API version this crate is built against, so the
+/// hostname is always the short name.
+pub fn hosts_file_content(pod: &KubePod, pod_ips: &[String], cluster_domain: &str) -> String {
+    let mut content = String::from(
+        "# Kubernetes-managed hosts file.\n\
+         127.0.0.1\tlocalhost\n\
+         ::1\tlocalhost ip6-localhost ip6-loopback\n\
+         fe00::0\tip6-localnet\n\
+         fe00::0\tip6-mcastprefix\n\
+         fe00::1\tip6-allnodes\n\
+         fe00::2\tip6-allrouters\n",
+    );
+    let hostname = namespaces::hostname(pod);
+    let names = match fqdn(pod, cluster_domain) {
+        Some(fqdn) => format!("{}\t{}", fqdn, hostname),
+        None => hostname,
+    };
+    for ip in pod_ips {
+        content.push_str(&format!("{}\t{}\n", ip, &names));
+    }
+
+    let host_aliases = pod
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.host_aliases.clone())
+        .unwrap_or_default();
+    if !host_aliases.is_empty() {
+        content.push_str("\n# Entries added by HostAliases.\n");
+        for alias in host_aliases {
+            let ip = match alias.ip {
+                Some(ip) => ip,
+                None => continue,
+            };
+            let hostnames = alias.hostnames.unwrap_or_default();
+            if !hostnames.is_empty() {
+                content.push_str(&format!("{}\t{}\n", ip, hostnames.join("\t")));
+            }
+        }
+    }
+    content
+}
+
+/// Writes the managed hosts file of a pod.
+pub async fn write_hosts_file(path: &Path, content: &str) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    tokio::fs::write(path, content).await?;
+    Ok(())
+}
+
+/// Returns the bind mount of the managed hosts file into a container. Pods using the host
+/// network see the node's hosts file, and containers may mount their own. Without a pod IP the
+/// file could not name the pod, so the runtime's hosts file is kept.
+pub fn hosts_mount(
+    pod: &KubePod,
+    pod_ips: &[String],
+    container: &Container,
+    path: &Path,
+) -> Option<cri::Mount> {
+    let mounts_hosts = container
+        .volume_mounts
+        .iter()
+        .flatten()
+        .any(|mount| mount.mount_path == HOSTS_PATH);
+    if namespaces::host_network(pod) || pod_ips.is_empty() || mounts_hosts {
+        return None;
+    }
+    Some(cri::Mount {
+        container_path: HOSTS_PATH.to_string(),
+        host_path: path.to_string_lossy().to_string(),
+        readonly: false,
+        selinux_relabel: false,
+        propagation: cri::MountPropagation::PropagationPrivate as i32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::kube_pod as pod;
+
+    /// Returns the lines following the fixed localhost entries.
+    fn entries(content: &str) -> Vec<&str> {
+        content.lines().skip(7).collect()
+    }
+
+    #[test]
+    fn maps_pod_ips_to_hostname() {
+        let content = hosts_file_content(
+            &pod(serde_json::json!({})),
+            &["10.244.0.5".to_string(), "fd00::5".to_string()],
+            "cluster.local",
+        );
+        assert!(content.starts_with("# Kubernetes-managed hosts file.\n127.0.0.1\tlocalhost\n"));
+        assert_eq!(
+            entries(&content),
+            vec!["10.244.0.5\tweb-0", "fd00::5\tweb-0"]
+        );
+    }
+
+    #[test]
+    fn includes_fqdn_with_subdomain() {
+        let content = hosts_file_content(
+            &pod(serde_json::json!({ "hostname": "db", "subdomain": "headless" })),
+            &["10.244.0.5".to_string()],
+            "cluster.local",
+        );
+        assert_eq!(
+            entries(&content),
+            vec!["10.244.0.5\tdb.headless.ns.svc.cluster.local\tdb"]
+        );
+    }
+
+    #[test]
+    fn appends_host_aliases() {
+        let content = hosts_file_content(
+            &pod(serde_json::json!({
+                "hostAliases": [
+                    { "ip": "127.0.0.1", "hostnames": ["foo.local", "bar.local"] },
+                    { "ip": "10.1.2.3", "hostnames": [] },
+                    { "hostnames": ["no-ip.local"] },
+                ],
+            })),
+            &[],
+            "cluster.local",
+        );
+        assert_eq!(
+            entries(&content),
+            vec![
+                "",
+                "# Entries added by HostAliases.",
+                "127.0.0.1\tfoo.local\tbar.local"
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_mount_when_not_managed() {
+        let path = Path::new("/var/lib/kubelet/pods/uid/etc-hosts");
+        let pod_ips = vec!["10.244.0.5".to_string()];
+        let container: Container =
+            serde_json::from_value(serde_json::json!({ "name": "app" })).unwrap();
+        assert!(hosts_mount(&pod(serde_json::json!({})), &pod_ips, &container, path).is_some());
+        assert!(hosts_mount(
+            &pod(serde_json::json!({ "hostNetwork": true })),
+            &pod_ips,
+            &container,
+            path
+        )
+        .is_none());
+        assert!(hosts_mount(&pod(serde_json::json!({})), &[], &container, path).is_none());
+        let container: Container = serde_json::from_value(serde_json::json!({
+            "name": "app",
+            "volumeMounts": [{ "name": "hosts", "mountPath": "/etc/hosts" }],
+        }))
+        .unwrap();
+        assert!(hosts_mount(&pod(serde_json::json!({})), &pod_ips, &container, path).is_none());
+    }
+}
@@ -0,0 +1,254 @@
+use k8s_cri::v1alpha2 as cri;
+use k8s_openapi::api::core::v1::{Container, Pod as KubePod};
+use std::collections::BTreeMap;
+
+const POD_NAME_LABEL: &str = "io.kubernetes.pod.name";
+const POD_NAMESPACE_LABEL: &str = "io.kubernetes.pod.namespace";
+const POD_UID_LABEL: &str = "io.kubernetes.pod.uid";
+const CONTAINER_NAME_LABEL: &str = "io.kubernetes.container.name";
+
+const CONTAINER_HASH_ANNOTATION: &str = "io.kubernetes.container.hash";
+const CONTAINER_RESTART_COUNT_ANNOTATION: &str = "io.kubernetes.container.restartCount";
+const CONTAINER_TERMINATION_MESSAGE_PATH_ANNOTATION: &str =
+    "io.kubernetes.container.terminationMessagePath";
+const CONTAINER_TERMINATION_MESSAGE_POLICY_ANNOTATION: &str =
+    "io.kubernetes.container.terminationMessagePolicy";
+const CONTAINER_PORTS_ANNOTATION: &str = "io.kubernetes.container.ports";
+const POD_DELETION_GRACE_PERIOD_ANNOTATION: &str = "io.kubernetes.pod.deletionGracePeriod";
+const POD_TERMINATION_GRACE_PERIOD_ANNOTATION: &str = "io.kubernetes.pod.terminationGracePeriod";
+
+const DEFAULT_TERMINATION_MESSAGE_PATH: &str = "/dev/termination-log";
+const DEFAULT_TERMINATION_MESSAGE_POLICY: &str = "File";
+
+/// Labels identifying the pod a sandbox or container belongs to.
+fn pod_labels(pod: &KubePod) -> BTreeMap<String, String> {
+    let mut labels = BTreeMap::new();
+    labels.insert(
+        POD_NAME_LABEL.to_string(),
+        pod.metadata.name.clone().unwrap_or_default(),
+    );
+    labels.insert(
+        POD_NAMESPACE_LABEL.to_string(),
+        pod.metadata
+            .namespace
+            .clone()
+            .unwrap_or_else(|| "default".to_string()),
+    );
+    labels.insert(
+        POD_UID_LABEL.to_string(),
+        pod.metadata.uid.clone().unwrap_or_default(),
+    );
+    labels
+}
+
+/// Returns the pod's labels, plus those identifying the pod.
+pub fn sandbox_labels(pod: &KubePod) -> BTreeMap<String, String> {
+    let mut labels = pod.metadata.labels.clone().unwrap_or_default();
+    labels.extend(pod_labels(pod));
+    labels
+}
+
+/// Returns the labels identifying a container and its pod.
+pub fn container_labels(pod: &KubePod, container_name: &str) -> BTreeMap<String, String> {
+    let mut labels = pod_labels(pod);
+    labels.insert(CONTAINER_NAME_LABEL.to_string(), container_name.to_string());
+    labels
+}
+
+/// Returns the annotations recording a container's state, so that it can be recovered from
+/// the runtime.
+pub fn container_annotations(
+    pod: &KubePod,
+    container: &Container,
+    restart_count: u32,
+) -> BTreeMap<String, String> {
+    let mut annotations = BTreeMap::new();
+    annotations.insert(
+        CONTAINER_HASH_ANNOTATION.to_string(),
+        container_hash(container),
+    );
+    annotations.insert(
+        CONTAINER_RESTART_COUNT_ANNOTATION.to_string(),
+        restart_count.to_string(),
+    );
+    annotations.insert(
+        CONTAINER_TERMINATION_MESSAGE_PATH_ANNOTATION.to_string(),
+        container
+            .termination_message_path
+            .clone()
+            .unwrap_or_else(|| DEFAULT_TERMINATION_MESSAGE_PATH.to_string()),
+    );
+    annotations.insert(
+        CONTAINER_TERMINATION_MESSAGE_POLICY_ANNOTATION.to_string(),
+        container
+            .termination_message_policy
+            .clone()
+            .unwrap_or_else(|| DEFAULT_TERMINATION_MESSAGE_POLICY.to_string()),
+    );
+    if let Some(ports) = container.ports.as_ref().filter(|ports| !ports.is_empty()) {
+        if let Ok(ports) = serde_json::to_string(ports) {
+            annotations.insert(CONTAINER_PORTS_ANNOTATION.to_string(), ports);
+        }
+    }
+    if let Some(seconds) = pod.metadata.deletion_grace_period_seconds {
+        annotations.insert(
+            POD_DELETION_GRACE_PERIOD_ANNOTATION.to_string(),
+            seconds.to_string(),
+        );
+    }
+    if let Some(seconds) = pod
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.termination_grace_period_seconds)
+    {
+        annotations.insert(
+            POD_TERMINATION_GRACE_PERIOD_ANNOTATION.to_string(),
+            seconds.to_string(),
+        );
+    }
+    annotations
+}
+
+/// Returns the restart count recorded on a container, or zero if it has none.
+pub fn restart_count(container: &cri::Container) -> u32 {
+    container
+        .annotations
+        .get(CONTAINER_RESTART_COUNT_ANNOTATION)
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Returns the restart count for the container replacing `container`. Only a container which
+/// was started counts as a restart; one which never ran keeps its count.
+pub fn next_restart_count(container: &cri::Container) -> u32 {
+    let running = cri::ContainerState::ContainerRunning as i32;
+    let exited = cri::ContainerState::ContainerExited as i32;
+    if container.state == running || container.state == exited {
+        restart_count(container) + 1
+    } else {
+        restart_count(container)
+    }
+}
+
+/// Hashes a container's spec, so that changes to it can be detected. This is the 32-bit FNV-1a
+/// hash of its JSON, which does not match the hash other kubelets record.
+fn container_hash(container: &Container) -> String {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in serde_json::to_vec(container).unwrap_or_default() {
+        hash ^= u32::from(byte);
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    format!("{:x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pod() -> KubePod {
+        let mut pod =
+            crate::testing::kube_pod(serde_json::json!({ "terminationGracePeriodSeconds": 30 }));
+        pod.metadata.labels = Some(
+            vec![("app", "web"), ("io.kubernetes.pod.name", "spoofed")]
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        );
+        pod.metadata.deletion_grace_period_seconds = Some(10);
+        pod
+    }
+
+    fn container(spec: serde_json::Value) -> Container {
+        serde_json::from_value(spec).unwrap()
+    }
+
+    #[test]
+    fn labels_sandboxes_with_pod_identity() {
+        let labels = sandbox_labels(&pod());
+        assert_eq!(labels["app"], "web");
+        // The pod's own labels cannot override those identifying it.
+        assert_eq!(labels[POD_NAME_LABEL], "web-0");
+        assert_eq!(labels[POD_NAMESPACE_LABEL], "ns");
+        assert_eq!(labels[POD_UID_LABEL], "1234");
+    }
+
+    #[test]
+    fn labels_containers_without_pod_labels() {
+        let labels = container_labels(&pod(), "app");
+        assert_eq!(labels[CONTAINER_NAME_LABEL], "app");
+        assert_eq!(labels[POD_UID_LABEL], "1234");
+        assert!(!labels.contains_key("app"));
+    }
+
+    #[test]
+    fn annotates_container_state() {
+        let container = container(serde_json::json!({
+            "name": "app",
+            "ports": [{ "containerPort": 80 }],
+        }));
+        let annotations = container_annotations(&pod(), &container, 3);
+        assert_eq!(annotations[CONTAINER_RESTART_COUNT_ANNOTATION], "3");
+        assert_eq!(
+            annotations[CONTAINER_TERMINATION_MESSAGE_PATH_ANNOTATION],
+            DEFAULT_TERMINATION_MESSAGE_PATH
+        );
+        assert_eq!(
+            annotations[CONTAINER_TERMINATION_MESSAGE_POLICY_ANNOTATION],
+            DEFAULT_TERMINATION_MESSAGE_POLICY
+        );
+        assert_eq!(
+            annotations[CONTAINER_PORTS_ANNOTATION],
+            r#"[{"containerPort":80}]"#
+        );
+        assert_eq!(annotations[POD_DELETION_GRACE_PERIOD_ANNOTATION], "10");
+        assert_eq!(annotations[POD_TERMINATION_GRACE_PERIOD_ANNOTATION], "30");
+    }
+
+    #[test]
+    fn reads_back_restart_counts() {
+        let mut runtime_container = cri::Container::default();
+        assert_eq!(restart_count(&runtime_container), 0);
+        runtime_container.annotations =
+            container_annotations(&pod(), &container(serde_json::json!({ "name": "app" })), 2)
+                .into_iter()
+                .collect();
+        assert_eq!(restart_count(&runtime_container), 2);
+    }
+
+    #[test]
+    fn counts_restarts_of_started_containers() {
+        let mut runtime_container = cri::Container {
+            annotations: container_annotations(
+                &pod(),
+                &container(serde_json::json!({ "name": "app" })),
+                2,
+            )
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        for state in &[
+            cri::ContainerState::ContainerRunning,
+            cri::ContainerState::ContainerExited,
+        ] {
+            runtime_container.state = *state as i32;
+            assert_eq!(next_restart_count(&runtime_container), 3);
+        }
+        for state in &[
+            cri::ContainerState::ContainerCreated,
+            cri::ContainerState::ContainerUnknown,
+        ] {
+            runtime_container.state = *state as i32;
+            assert_eq!(next_restart_count(&runtime_container), 2);
+        }
+    }
+
+    #[test]
+    fn hashes_container_specs() {
+        let app = container(serde_json::json!({ "name": "app", "image": "nginx:1.19" }));
+        let same = container(serde_json::json!({ "name": "app", "image": "nginx:1.19" }));
+        let changed = container(serde_json::json!({ "name": "app", "image": "nginx:1.20" }));
+        assert_eq!(container_hash(&app), container_hash(&same));
+        assert_ne!(container_hash(&app), container_hash(&changed));
+    }
+}
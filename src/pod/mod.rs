@@ -0,0 +1,14 @@
+//! Translation of Kubernetes Pod specs into CRI configuration.
+pub mod dns;
+pub mod downward;
+pub mod env;
+pub mod events;
+pub mod expansion;
+pub mod hosts;
+pub mod labels;
+pub mod namespaces;
+pub mod ports;
+pub mod qos;
+pub mod resources;
+pub mod runtime_class;
+pub mod security_context;
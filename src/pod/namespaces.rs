@@ -0,0 +1,133 @@
+use k8s_cri::v1alpha2 as cri;
+use k8s_openapi::api::core::v1::Pod as KubePod;
+
+/// Longest hostname accepted by the kernel.
+const MAX_HOSTNAME_LEN: usize = 63;
+
+/// Chooses the network, PID and IPC namespaces of a pod sandbox.
+pub fn namespace_options(pod: &KubePod) -> cri::NamespaceOption {
+    let spec = pod.spec.clone().unwrap_or_default();
+    let mode = |host: Option<bool>, otherwise: cri::NamespaceMode| {
+        if host.unwrap_or(false) {
+            cri::NamespaceMode::Node as i32
+        } else {
+            otherwise as i32
+        }
+    };
+    let pid = if spec.share_process_namespace.unwrap_or(false) {
+        cri::NamespaceMode::Pod
+    } else {
+        cri::NamespaceMode::Container
+    };
+    cri::NamespaceOption {
+        network: mode(spec.host_network, cri::NamespaceMode::Pod),
+        pid: mode(spec.host_pid, pid),
+        ipc: mode(spec.host_ipc, cri::NamespaceMode::Pod),
+        target_id: String::new(),
+    }
+}
+
+pub fn host_network(pod: &KubePod) -> bool {
+    pod.spec
+        .as_ref()
+        .and_then(|spec| spec.host_network)
+        .unwrap_or(false)
+}
+
+/// Returns the sandbox hostname, `spec.hostname` or else the pod name.
+///
+/// Pods using the host network are left with the node's hostname, which the runtime keeps when
+/// the hostname is empty.
+pub fn hostname(pod: &KubePod) -> String {
+    if host_network(pod) {
+        return String::new();
+    }
+    let hostname = pod
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.hostname.clone())
+        .filter(|hostname| !hostname.is_empty())
+        .or_else(|| pod.metadata.name.clone())
+        .unwrap_or_default();
+    if hostname.len() <= MAX_HOSTNAME_LEN {
+        return hostname;
+    }
+    // Pod names are DNS subdomains and so ASCII. Truncation may leave a trailing separator,
+    // which is not valid at the end of a hostname.
+    hostname[..MAX_HOSTNAME_LEN]
+        .trim_end_matches(['-', '.'])
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::kube_pod;
+    use serde_json::json;
+
+    fn modes(options: &cri::NamespaceOption) -> (i32, i32, i32) {
+        (options.network, options.pid, options.ipc)
+    }
+
+    #[test]
+    fn isolates_pods_by_default() {
+        let options = namespace_options(&kube_pod(json!({})));
+        assert_eq!(
+            modes(&options),
+            (
+                cri::NamespaceMode::Pod as i32,
+                cri::NamespaceMode::Container as i32,
+                cri::NamespaceMode::Pod as i32,
+            )
+        );
+    }
+
+    #[test]
+    fn uses_host_namespaces() {
+        let options = namespace_options(&kube_pod(json!({
+            "hostNetwork": true,
+            "hostPID": true,
+            "hostIPC": true,
+        })));
+        let node = cri::NamespaceMode::Node as i32;
+        assert_eq!(modes(&options), (node, node, node));
+    }
+
+    #[test]
+    fn shares_the_process_namespace() {
+        let options = namespace_options(&kube_pod(json!({ "shareProcessNamespace": true })));
+        assert_eq!(options.pid, cri::NamespaceMode::Pod as i32);
+        // hostPID takes precedence.
+        let options = namespace_options(&kube_pod(json!({
+            "shareProcessNamespace": true,
+            "hostPID": true,
+        })));
+        assert_eq!(options.pid, cri::NamespaceMode::Node as i32);
+    }
+
+    #[test]
+    fn chooses_the_hostname() {
+        assert_eq!(hostname(&kube_pod(json!({}))), "web-0");
+        assert_eq!(hostname(&kube_pod(json!({ "hostname": "db" }))), "db");
+        assert_eq!(hostname(&kube_pod(json!({ "hostname": "" }))), "web-0");
+        assert_eq!(
+            hostname(&kube_pod(json!({ "hostname": "db", "hostNetwork": true }))),
+            ""
+        );
+    }
+
+    #[test]
+    fn truncates_long_hostnames() {
+        let long = "a".repeat(70);
+        assert_eq!(
+            hostname(&kube_pod(json!({ "hostname": long }))),
+            "a".repeat(63)
+        );
+        // A separator left at the end by truncation is dropped.
+        let long = format!("{}-.{}", "a".repeat(61), "b".repeat(10));
+        assert_eq!(
+            hostname(&kube_pod(json!({ "hostname": long }))),
+            "a".repeat(61)
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use k8s_cri::v1alpha2 as cri;
+use k8s_openapi::api::core::v1::Pod as KubePod;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Translates container ports which declare a `hostPort` into sandbox port mappings.
+pub fn port_mappings(pod: &KubePod) -> anyhow::Result<Vec<cri::PortMapping>> {
+    let mut mappings = vec![];
+    let containers = pod
+        .spec
+        .as_ref()
+        .map(|spec| spec.containers.as_slice())
+        .unwrap_or_default();
+    for container in containers {
+        for port in container.ports.iter().flatten() {
+            let host_port = match port.host_port {
+                Some(host_port) if host_port > 0 => host_port,
+                _ => continue,
+            };
+            let protocol = match port.protocol.as_deref().unwrap_or("TCP") {
+                "TCP" => cri::Protocol::Tcp,
+                "UDP" => cri::Protocol::Udp,
+                "SCTP" => cri::Protocol::Sctp,
+                protocol => anyhow::bail!(
+                    "Unsupported protocol {} for port {} of container {}.",
+                    protocol,
+                    port.container_port,
+                    &container.name
+                ),
+            };
+            mappings.push(cri::PortMapping {
+                protocol: protocol as i32,
+                container_port: port.container_port,
+                host_port,
+                host_ip: port.host_ip.clone().unwrap_or_default(),
+            });
+        }
+    }
+    Ok(mappings)
+}
+
+/// Host ports claimed by pods admitted to this node, keyed by pod UID.
+#[derive(Clone, Default)]
+pub struct HostPorts {
+    claims: Arc<Mutex<HashMap<String, Vec<cri::PortMapping>>>>,
+}
+
+impl HostPorts {
+    /// Claims the host ports of a pod, failing if another pod already holds any of them.
+    pub async fn claim(&self, uid: String, mappings: &[cri::PortMapping]) -> anyhow::Result<()> {
+        let mut claims = self.claims.lock().await;
+        for (owner, claimed) in claims.iter() {
+            if owner == &uid {
+                continue;
+            }
+            for mapping in mappings {
+                if claimed.iter().any(|claimed| conflicts(claimed, mapping)) {
+                    anyhow::bail!(
+                        "Host port {}/{} on {} is already in use on this node.",
+                        mapping.host_port,
+                        protocol_name(mapping.protocol),
+                        if mapping.host_ip.is_empty() {
+                            "0.0.0.0"
+                        } else {
+                            mapping.host_ip.as_str()
+                        }
+                    );
+                }
+            }
+        }
+        if !mappings.is_empty() {
+            claims.insert(uid, mappings.to_vec());
+        }
+        Ok(())
+    }
+
+    pub async fn release(&self, uid: &str) {
+        self.claims.lock().await.remove(uid);
+    }
+}
+
+/// Whether two mappings bind the same port, where an unspecified host IP binds every address.
+fn conflicts(a: &cri::PortMapping, b: &cri::PortMapping) -> bool {
+    let unspecified = |ip: &str| ip.is_empty() || ip == "0.0.0.0" || ip == "::";
+    a.protocol == b.protocol
+        && a.host_port == b.host_port
+        && (a.host_ip == b.host_ip || unspecified(&a.host_ip) || unspecified(&b.host_ip))
+}
+
+fn protocol_name(protocol: i32) -> &'static str {
+    match protocol {
+        p if p == cri::Protocol::Udp as i32 => "UDP",
+        p if p == cri::Protocol::Sctp as i32 => "SCTP",
+        _ => "TCP",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pod(ports: serde_json::Value) -> KubePod {
+        crate::testing::kube_pod(serde_json::json!({
+            "containers": [{ "name": "app", "ports": ports }],
+        }))
+    }
+
+    fn mapping(protocol: cri::Protocol, host_port: i32, host_ip: &str) -> cri::PortMapping {
+        cri::PortMapping {
+            protocol: protocol as i32,
+            container_port: 80,
+            host_port,
+            host_ip: host_ip.to_string(),
+        }
+    }
+
+    fn owner(uid: &str) -> String {
+        uid.to_string()
+    }
+
+    #[test]
+    fn maps_only_host_ports() {
+        let mappings = port_mappings(&pod(serde_json::json!([
+            { "containerPort": 80, "hostPort": 8080 },
+            { "containerPort": 53, "hostPort": 53, "protocol": "UDP", "hostIP": "10.0.0.1" },
+            { "containerPort": 9090 },
+            { "containerPort": 9091, "hostPort": 0 },
+        ])))
+        .unwrap();
+        assert_eq!(
+            mappings,
+            vec![
+                mapping(cri::Protocol::Tcp, 8080, ""),
+                cri::PortMapping {
+                    container_port: 53,
+                    ..mapping(cri::Protocol::Udp, 53, "10.0.0.1")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_protocols() {
+        let pod = pod(serde_json::json!([
+            { "containerPort": 80, "hostPort": 8080, "protocol": "QUIC" },
+        ]));
+        assert!(port_mappings(&pod).is_err());
+    }
+
+    #[test]
+    fn detects_conflicts() {
+        let any = mapping(cri::Protocol::Tcp, 8080, "");
+        assert!(conflicts(
+            &any,
+            &mapping(cri::Protocol::Tcp, 8080, "10.0.0.1")
+        ));
+        assert!(conflicts(
+            &mapping(cri::Protocol::Tcp, 8080, "::"),
+            &mapping(cri::Protocol::Tcp, 8080, "10.0.0.1")
+        ));
+        assert!(!conflicts(&any, &mapping(cri::Protocol::Udp, 8080, "")));
+        assert!(!conflicts(&any, &mapping(cri::Protocol::Tcp, 8081, "")));
+        assert!(!conflicts(
+            &mapping(cri::Protocol::Tcp, 8080, "10.0.0.1"),
+            &mapping(cri::Protocol::Tcp, 8080, "10.0.0.2")
+        ));
+    }
+
+    #[tokio::test]
+    async fn claims_host_ports_once() {
+        let host_ports = HostPorts::default();
+        let mappings = vec![mapping(cri::Protocol::Tcp, 8080, "")];
+        host_ports.claim(owner("a"), &mappings).await.unwrap();
+        // Claiming again for the same pod is not a conflict.
+        host_ports.claim(owner("a"), &mappings).await.unwrap();
+        assert!(host_ports.claim(owner("b"), &mappings).await.is_err());
+        host_ports
+            .claim(owner("b"), &[mapping(cri::Protocol::Udp, 8080, "")])
+            .await
+            .unwrap();
+
+        host_ports.release(&owner("a")).await;
+        host_ports.claim(owner("c"), &mappings).await.unwrap();
+    }
+}
@@ -0,0 +1,240 @@
+use k8s_openapi::api::core::v1::{Container, Pod as KubePod};
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use std::collections::BTreeMap;
+
+use crate::config::CgroupDriver;
+use crate::quantity;
+
+/// Resources which determine a pod's QoS class.
+const QOS_RESOURCES: &[&str] = &["cpu", "memory"];
+
+const GUARANTEED_OOM_SCORE_ADJ: i64 = -997;
+const BEST_EFFORT_OOM_SCORE_ADJ: i64 = 1000;
+
+/// Quality of Service class, which decides how a pod is treated under resource pressure.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum QosClass {
+    Guaranteed,
+    Burstable,
+    #[default]
+    BestEffort,
+}
+
+impl QosClass {
+    /// Classifies a pod from the CPU and memory requests and limits of its containers.
+    ///
+    /// A pod is Guaranteed when every container limits both CPU and memory, and requests
+    /// equal limits. It is BestEffort when no container requests or limits either.
+    pub fn of(pod: &KubePod) -> anyhow::Result<Self> {
+        let spec = pod.spec.clone().unwrap_or_default();
+        let mut requests: BTreeMap<&str, i128> = BTreeMap::new();
+        let mut limits: BTreeMap<&str, i128> = BTreeMap::new();
+        let mut guaranteed = true;
+        let containers = spec
+            .init_containers
+            .iter()
+            .flatten()
+            .chain(spec.containers.iter());
+        for container in containers {
+            let resources = container.resources.clone().unwrap_or_default();
+            let container_requests = qos_resources(resources.requests.as_ref())?;
+            let container_limits = qos_resources(resources.limits.as_ref())?;
+            for (name, value) in &container_requests {
+                *requests.entry(*name).or_insert(0) += value;
+            }
+            for (name, value) in &container_limits {
+                *limits.entry(*name).or_insert(0) += value;
+            }
+            if container_limits.len() != QOS_RESOURCES.len() {
+                guaranteed = false;
+            }
+        }
+
+        if requests.is_empty() && limits.is_empty() {
+            return Ok(QosClass::BestEffort);
+        }
+        if guaranteed
+            && requests.len() == limits.len()
+            && requests
+                .iter()
+                .all(|(name, request)| limits.get(name) == Some(request))
+        {
+            return Ok(QosClass::Guaranteed);
+        }
+        Ok(QosClass::Burstable)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QosClass::Guaranteed => "Guaranteed",
+            QosClass::Burstable => "Burstable",
+            QosClass::BestEffort => "BestEffort",
+        }
+    }
+
+    /// Returns the pod's cgroup under the kubepods hierarchy.
+    ///
+    /// Guaranteed pods sit directly under kubepods, the other classes under a cgroup of their
+    /// own, such as `/kubepods/burstable/pod<uid>` or `kubepods-burstable-pod<uid>.slice`.
+    pub fn cgroup_parent(self, uid: &str, driver: CgroupDriver) -> String {
+        let class = match self {
+            QosClass::Guaranteed => None,
+            QosClass::Burstable => Some("burstable"),
+            QosClass::BestEffort => Some("besteffort"),
+        };
+        match driver {
+            CgroupDriver::Cgroupfs => match class {
+                Some(class) => format!("/kubepods/{}/pod{}", class, uid),
+                None => format!("/kubepods/pod{}", uid),
+            },
+            // Dashes separate the levels of a systemd slice name, so they are escaped in the UID.
+            CgroupDriver::Systemd => match class {
+                Some(class) => format!("kubepods-{}-pod{}.slice", class, uid.replace("-", "_")),
+                None => format!("kubepods-pod{}.slice", uid.replace("-", "_")),
+            },
+        }
+    }
+
+    /// Returns the OOM score adjustment for a container, so that the kernel kills BestEffort
+    /// containers first and Guaranteed ones last. Burstable containers are scored by the share
+    /// of node memory they request.
+    pub fn oom_score_adj(self, container: &Container, memory_capacity: Option<i128>) -> i64 {
+        match self {
+            QosClass::Guaranteed => GUARANTEED_OOM_SCORE_ADJ,
+            QosClass::BestEffort => BEST_EFFORT_OOM_SCORE_ADJ,
+            QosClass::Burstable => {
+                let request = container
+                    .resources
+                    .as_ref()
+                    .and_then(|resources| resources.requests.as_ref())
+                    .and_then(|requests| requests.get("memory"))
+                    .and_then(|memory| quantity::value(memory).ok())
+                    .unwrap_or(0);
+                let score = match memory_capacity {
+                    Some(capacity) if capacity > 0 => 1000 - 1000 * request / capacity,
+                    _ => 1000,
+                };
+                // Stay above Guaranteed and system daemons, and below BestEffort.
+                (score as i64).clamp(3, 999)
+            }
+        }
+    }
+}
+
+/// Returns the non-zero CPU and memory quantities of a resource list, in thousandths.
+fn qos_resources(
+    list: Option<&BTreeMap<String, Quantity>>,
+) -> anyhow::Result<BTreeMap<&'static str, i128>> {
+    let mut resources = BTreeMap::new();
+    for name in QOS_RESOURCES {
+        if let Some(value) = list.and_then(|list| list.get(*name)) {
+            let value = quantity::milli_value(value)?;
+            if value != 0 {
+                resources.insert(*name, value);
+            }
+        }
+    }
+    Ok(resources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pod_of(resources: &[serde_json::Value]) -> KubePod {
+        let containers: Vec<_> = resources
+            .iter()
+            .enumerate()
+            .map(|(i, resources)| {
+                serde_json::json!({ "name": format!("c{}", i), "resources": resources })
+            })
+            .collect();
+        crate::testing::kube_pod(serde_json::json!({ "containers": containers }))
+    }
+
+    fn pod_with(resources: serde_json::Value) -> KubePod {
+        pod_of(&[resources])
+    }
+
+    #[test]
+    fn classifies_best_effort() {
+        let pod = pod_of(&[serde_json::json!({}), serde_json::json!({})]);
+        assert_eq!(QosClass::of(&pod).unwrap(), QosClass::BestEffort);
+        // Zero requests and other resources do not count.
+        let pod = pod_with(serde_json::json!({
+            "requests": { "cpu": "0", "ephemeral-storage": "1Gi" },
+        }));
+        assert_eq!(QosClass::of(&pod).unwrap(), QosClass::BestEffort);
+    }
+
+    #[test]
+    fn classifies_guaranteed() {
+        let resources = serde_json::json!({
+            "requests": { "cpu": "500m", "memory": "128Mi" },
+            "limits": { "cpu": "0.5", "memory": "128Mi" },
+        });
+        let pod = pod_of(&[resources.clone(), resources]);
+        assert_eq!(QosClass::of(&pod).unwrap(), QosClass::Guaranteed);
+    }
+
+    #[test]
+    fn classifies_burstable() {
+        let pod = pod_with(serde_json::json!({ "requests": { "memory": "128Mi" } }));
+        assert_eq!(QosClass::of(&pod).unwrap(), QosClass::Burstable);
+        let pod = pod_with(serde_json::json!({
+            "requests": { "cpu": "250m", "memory": "128Mi" },
+            "limits": { "cpu": "500m", "memory": "128Mi" },
+        }));
+        assert_eq!(QosClass::of(&pod).unwrap(), QosClass::Burstable);
+        // Every container must be limited for the pod to be Guaranteed.
+        let pod = pod_of(&[
+            serde_json::json!({ "limits": { "cpu": "1", "memory": "1Gi" } }),
+            serde_json::json!({}),
+        ]);
+        assert_eq!(QosClass::of(&pod).unwrap(), QosClass::Burstable);
+    }
+
+    #[test]
+    fn rejects_invalid_quantities() {
+        let pod = pod_with(serde_json::json!({ "requests": { "cpu": "lots" } }));
+        assert!(QosClass::of(&pod).is_err());
+    }
+
+    #[test]
+    fn builds_cgroup_parents() {
+        let uid = "1234-abcd";
+        assert_eq!(
+            QosClass::Guaranteed.cgroup_parent(uid, CgroupDriver::Cgroupfs),
+            "/kubepods/pod1234-abcd"
+        );
+        assert_eq!(
+            QosClass::Burstable.cgroup_parent(uid, CgroupDriver::Cgroupfs),
+            "/kubepods/burstable/pod1234-abcd"
+        );
+        assert_eq!(
+            QosClass::Guaranteed.cgroup_parent(uid, CgroupDriver::Systemd),
+            "kubepods-pod1234_abcd.slice"
+        );
+        assert_eq!(
+            QosClass::BestEffort.cgroup_parent(uid, CgroupDriver::Systemd),
+            "kubepods-besteffort-pod1234_abcd.slice"
+        );
+    }
+
+    #[test]
+    fn scores_burstable_containers_by_memory_request() {
+        let container: Container = serde_json::from_value(serde_json::json!({
+            "name": "c",
+            "resources": { "requests": { "memory": "1Gi" } },
+        }))
+        .unwrap();
+        let capacity = Some(4 * 1024 * 1024 * 1024);
+        assert_eq!(QosClass::Burstable.oom_score_adj(&container, capacity), 750);
+        assert_eq!(QosClass::Burstable.oom_score_adj(&container, Some(1024)), 3);
+        assert_eq!(QosClass::Burstable.oom_score_adj(&container, None), 999);
+        assert_eq!(
+            QosClass::Guaranteed.oom_score_adj(&container, capacity),
+            GUARANTEED_OOM_SCORE_ADJ
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use k8s_cri::v1alpha2 as cri;
+use k8s_openapi::api::core::v1::Container;
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::qos::QosClass;
+use crate::node::capacity::Resources;
+use crate::quantity;
+
+const MIN_SHARES: i64 = 2;
+const SHARES_PER_CPU: i64 = 1024;
+const MILLI_CPU_TO_CPU: i64 = 1000;
+/// The smallest quota the kernel accepts, in microseconds.
+const MIN_QUOTA_PERIOD: i64 = 1000;
+
+const HUGEPAGES_PREFIX: &str = "hugepages-";
+const HUGEPAGE_UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB", "PB"];
+
+/// Returns the cgroup resources of a container from its requests and limits.
+///
+/// CPU requests become shares, falling back to the limit when only that is set. CPU limits
+/// become a CFS quota over `cfs_period`, which is `None` when they are not enforced. Hugepage
+/// sizes available on the node are limited to zero unless the container has a limit for them.
+pub fn container_resources(
+    container: &Container,
+    qos_class: QosClass,
+    memory_capacity: Option<i128>,
+    allocatable: &Resources,
+    cfs_period: Option<Duration>,
+) -> anyhow::Result<cri::LinuxContainerResources> {
+    let resources = container.resources.clone().unwrap_or_default();
+    let requests = resources.requests.unwrap_or_default();
+    let limits = resources.limits.unwrap_or_default();
+
+    let cpu_request = milli_value(&requests, "cpu")?;
+    let cpu_limit = milli_value(&limits, "cpu")?;
+    let cpu_shares = if cpu_request == 0 && cpu_limit != 0 {
+        milli_cpu_to_shares(cpu_limit)
+    } else {
+        milli_cpu_to_shares(cpu_request)
+    };
+    let (cpu_quota, cpu_period) = match cfs_period {
+        Some(period) => {
+            let period = period.as_micros() as i64;
+            (milli_cpu_to_quota(cpu_limit, period), period)
+        }
+        None => (0, 0),
+    };
+
+    let memory_limit_in_bytes = match limits.get("memory") {
+        Some(memory) => clamp(quantity::value(memory)?),
+        None => 0,
+    };
+
+    Ok(cri::LinuxContainerResources {
+        cpu_period,
+        cpu_quota,
+        cpu_shares,
+        memory_limit_in_bytes,
+        oom_score_adj: qos_class.oom_score_adj(container, memory_capacity),
+        hugepage_limits: hugepage_limits(&limits, allocatable)?,
+        ..Default::default()
+    })
+}
+
+fn milli_value(list: &BTreeMap<String, Quantity>, name: &str) -> anyhow::Result<i64> {
+    match list.get(name) {
+        Some(value) => Ok(clamp(quantity::milli_value(value)?)),
+        None => Ok(0),
+    }
+}
+
+fn clamp(value: i128) -> i64 {
+    std::cmp::min(std::cmp::max(value, 0), i128::from(i64::MAX)) as i64
+}
+
+/// Converts thousandths of a CPU to CPU shares, where a whole CPU is 1024 shares.
+fn milli_cpu_to_shares(milli_cpu: i64) -> i64 {
+    if milli_cpu == 0 {
+        // Containers without a request get the minimum, rather than the runtime's default.
+        return MIN_SHARES;
+    }
+    std::cmp::max(milli_cpu * SHARES_PER_CPU / MILLI_CPU_TO_CPU, MIN_SHARES)
+}
+
+/// Converts thousandths of a CPU to a CFS quota over `period` microseconds. No limit is zero.
+fn milli_cpu_to_quota(milli_cpu: i64, period: i64) -> i64 {
+    if milli_cpu == 0 {
+        return 0;
+    }
+    std::cmp::max(milli_cpu * period / MILLI_CPU_TO_CPU, MIN_QUOTA_PERIOD)
+}
+
+/// Returns the hugepage limits of a container, keyed by the page sizes the node offers.
+fn hugepage_limits(
+    limits: &BTreeMap<String, Quantity>,
+    allocatable: &Resources,
+) -> anyhow::Result<Vec<cri::HugepageLimit>> {
+    let mut hugepage_limits = BTreeMap::new();
+    for name in allocatable.keys() {
+        if let Some(page_size) = hugepage_size(name)? {
+            hugepage_limits.insert(page_size, 0);
+        }
+    }
+    for (name, limit) in limits {
+        if let Some(page_size) = hugepage_size(name)? {
+            hugepage_limits.insert(page_size, quantity::value(limit)?.max(0) as u64);
+        }
+    }
+    Ok(hugepage_limits
+        .into_iter()
+        .map(|(page_size, limit)| cri::HugepageLimit { page_size, limit })
+        .collect())
+}
+
+/// Returns the CRI page size, such as `2MB`, of a resource name such as `hugepages-2Mi`.
+fn hugepage_size(resource_name: &str) -> anyhow::Result<Option<String>> {
+    if !resource_name.starts_with(HUGEPAGES_PREFIX) {
+        return Ok(None);
+    }
+    let size = quantity::parse_millis(&resource_name[HUGEPAGES_PREFIX.len()..])? / 1000;
+    Ok(Some(hugepage_unit_size(size)?))
+}
+
+/// Formats a page size in bytes with the largest binary unit which divides it.
+fn hugepage_unit_size(size: i128) -> anyhow::Result<String> {
+    if size <= 0 {
+        anyhow::bail!("Invalid hugepage size {}.", size);
+    }
+    let mut size = size;
+    let mut unit = 0;
+    while size % 1024 == 0 && unit < HUGEPAGE_UNITS.len() - 1 {
+        size /= 1024;
+        unit += 1;
+    }
+    Ok(format!("{}{}", size, HUGEPAGE_UNITS[unit]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::api::core::v1::ResourceRequirements;
+
+    const PERIOD: Option<Duration> = Some(Duration::from_millis(100));
+
+    fn container(requests: &[(&str, &str)], limits: &[(&str, &str)]) -> Container {
+        let list = |pairs: &[(&str, &str)]| {
+            Some(
+                pairs
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), Quantity(value.to_string())))
+                    .collect(),
+            )
+        };
+        Container {
+            name: "test".to_string(),
+            resources: Some(ResourceRequirements {
+                requests: list(requests),
+                limits: list(limits),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn converts_milli_cpu_to_shares() {
+        assert_eq!(milli_cpu_to_shares(0), 2);
+        assert_eq!(milli_cpu_to_shares(1), 2);
+        assert_eq!(milli_cpu_to_shares(100), 102);
+        assert_eq!(milli_cpu_to_shares(250), 256);
+        assert_eq!(milli_cpu_to_shares(1000), 1024);
+        assert_eq!(milli_cpu_to_shares(2500), 2560);
+    }
+
+    #[test]
+    fn converts_milli_cpu_to_quota() {
+        assert_eq!(milli_cpu_to_quota(0, 100_000), 0);
+        assert_eq!(milli_cpu_to_quota(5, 100_000), 1000);
+        assert_eq!(milli_cpu_to_quota(9, 100_000), 1000);
+        assert_eq!(milli_cpu_to_quota(10, 100_000), 1000);
+        assert_eq!(milli_cpu_to_quota(200, 100_000), 20_000);
+        assert_eq!(milli_cpu_to_quota(500, 100_000), 50_000);
+        assert_eq!(milli_cpu_to_quota(1000, 100_000), 100_000);
+        assert_eq!(milli_cpu_to_quota(1500, 100_000), 150_000);
+        assert_eq!(milli_cpu_to_quota(1500, 50_000), 75_000);
+    }
+
+    #[test]
+    fn formats_hugepage_unit_sizes() {
+        assert_eq!(hugepage_unit_size(1024).unwrap(), "1KB");
+        assert_eq!(hugepage_unit_size(2 * 1024 * 1024).unwrap(), "2MB");
+        assert_eq!(hugepage_unit_size(1024 * 1024 * 1024).unwrap(), "1GB");
+        assert_eq!(hugepage_unit_size(1536).unwrap(), "1536B");
+        assert!(hugepage_unit_size(0).is_err());
+        assert_eq!(hugepage_size("hugepages-2Mi").unwrap().unwrap(), "2MB");
+        assert_eq!(hugepage_size("memory").unwrap(), None);
+    }
+
+    #[test]
+    fn maps_requests_and_limits() {
+        let container = container(
+            &[("cpu", "250m"), ("memory", "128Mi")],
+            &[
+                ("cpu", "1500m"),
+                ("memory", "256Mi"),
+                ("hugepages-2Mi", "4Mi"),
+            ],
+        );
+        let mut allocatable = Resources::new();
+        allocatable.insert("hugepages-2Mi".to_string(), 0);
+        allocatable.insert("hugepages-1Gi".to_string(), 0);
+        let resources = container_resources(
+            &container,
+            QosClass::Burstable,
+            Some(1024 * 1024 * 1024),
+            &allocatable,
+            PERIOD,
+        )
+        .unwrap();
+        assert_eq!(resources.cpu_shares, 256);
+        assert_eq!(resources.cpu_quota, 150_000);
+        assert_eq!(resources.cpu_period, 100_000);
+        assert_eq!(resources.memory_limit_in_bytes, 256 * 1024 * 1024);
+        assert_eq!(resources.oom_score_adj, 875);
+        let hugepage_limits: Vec<_> = resources
+            .hugepage_limits
+            .iter()
+            .map(|limit| (limit.page_size.as_str(), limit.limit))
+            .collect();
+        assert_eq!(hugepage_limits, vec![("1GB", 0), ("2MB", 4 * 1024 * 1024)]);
+    }
+
+    #[test]
+    fn defaults_shares_to_the_cpu_limit() {
+        let container = container(&[], &[("cpu", "500m")]);
+        let resources = container_resources(
+            &container,
+            QosClass::Burstable,
+            None,
+            &Resources::new(),
+            PERIOD,
+        )
+        .unwrap();
+        assert_eq!(resources.cpu_shares, 512);
+        assert_eq!(resources.cpu_quota, 50_000);
+        assert_eq!(resources.memory_limit_in_bytes, 0);
+    }
+
+    #[test]
+    fn leaves_unlimited_containers_unbounded() {
+        let container = container(&[], &[]);
+        let resources = container_resources(
+            &container,
+            QosClass::BestEffort,
+            None,
+            &Resources::new(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(resources.cpu_shares, 2);
+        assert_eq!(resources.cpu_quota, 0);
+        assert_eq!(resources.cpu_period, 0);
+        assert_eq!(resources.memory_limit_in_bytes, 0);
+        assert_eq!(resources.oom_score_adj, 1000);
+        assert!(resources.hugepage_limits.is_empty());
+    }
+}
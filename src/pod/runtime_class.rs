@@ -0,0 +1,228 @@
+use k8s_openapi::api::core::v1::Node;
+use k8s_openapi::api::node::v1beta1::RuntimeClass;
+use log::{debug, info, warn};
+
+/// Runtime settings resolved from a Pod's RuntimeClass.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RuntimeClassConfig {
+    /// CRI runtime handler, empty for the runtime default.
+    pub handler: String,
+}
+
+/// A RuntimeClass which cannot be used on this node, so the pod will never start here.
+#[derive(Debug, PartialEq)]
+pub enum Unusable {
+    NotFound {
+        name: String,
+    },
+    NodeSelector {
+        name: String,
+        key: String,
+        value: String,
+    },
+}
+
+impl std::fmt::Display for Unusable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unusable::NotFound { name } => write!(f, "RuntimeClass \"{}\" not found.", name),
+            Unusable::NodeSelector { name, key, value } => write!(
+                f,
+                "RuntimeClass \"{}\" requires node label {}={}.",
+                name, key, value
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Unusable {}
+
+/// Resolves `spec.runtimeClassName` through the `node.k8s.io` API.
+///
+/// Fails with [`Unusable`] if the RuntimeClass does not exist or its `scheduling.nodeSelector`
+/// does not match this node, as the handler is then not expected to be installed here. Any other
+/// error is transient. Tolerations are only relevant to the scheduler and are not checked.
+///
+/// `overhead` is not enforced. CRI `v1alpha2` cannot pass sandbox resources to the runtime, and
+/// KrustletCRI does not size pod cgroups itself, so pods which declare an overhead are run
+/// without it and a warning is logged.
+pub async fn resolve(
+    client: kube::Client,
+    node_name: &str,
+    pod: &kubelet::pod::Pod,
+) -> anyhow::Result<RuntimeClassConfig> {
+    let spec = pod.as_kube_pod().spec.clone().unwrap_or_default();
+    let name = match spec.runtime_class_name {
+        Some(name) => name,
+        None => return Ok(RuntimeClassConfig::default()),
+    };
+
+    debug!("Resolving RuntimeClass {}.", &name);
+    let runtime_classes: kube::Api<RuntimeClass> = kube::Api::all(client.clone());
+    let runtime_class = match runtime_classes.get(&name).await {
+        Ok(runtime_class) => runtime_class,
+        Err(kube::Error::Api(e)) if e.code == 404 => anyhow::bail!(Unusable::NotFound { name }),
+        Err(e) => anyhow::bail!(e),
+    };
+
+    if let Some(node_selector) = runtime_class
+        .scheduling
+        .as_ref()
+        .and_then(|scheduling| scheduling.node_selector.as_ref())
+    {
+        let nodes: kube::Api<Node> = kube::Api::all(client);
+        let labels = nodes
+            .get(node_name)
+            .await?
+            .metadata
+            .labels
+            .unwrap_or_default();
+        for (key, value) in node_selector {
+            if labels.get(key) != Some(value) {
+                anyhow::bail!(Unusable::NodeSelector {
+                    name,
+                    key: key.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+    }
+
+    // The RuntimeClass admission controller normally copies the overhead into the Pod spec.
+    let overhead = spec.overhead.or_else(|| {
+        runtime_class
+            .overhead
+            .as_ref()
+            .and_then(|overhead| overhead.pod_fixed.clone())
+    });
+    if let Some(overhead) = overhead.filter(|overhead| !overhead.is_empty()) {
+        warn!(
+            "Pod {} overhead {:?} from RuntimeClass {} is not enforced.",
+            pod.name(),
+            &overhead,
+            &name
+        );
+    }
+
+    info!(
+        "Using runtime handler {} for RuntimeClass {}.",
+        &runtime_class.handler, &name
+    );
+    Ok(RuntimeClassConfig {
+        handler: runtime_class.handler,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{api_server, pod};
+    use serde_json::json;
+
+    fn gvisor(scheduling: serde_json::Value) -> serde_json::Value {
+        json!({
+            "apiVersion": "node.k8s.io/v1beta1",
+            "kind": "RuntimeClass",
+            "metadata": {"name": "gvisor"},
+            "handler": "runsc",
+            "scheduling": scheduling,
+        })
+    }
+
+    fn node() -> serde_json::Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "Node",
+            "metadata": {"name": "node-1", "labels": {"sandbox": "gvisor"}},
+        })
+    }
+
+    fn gvisor_pod() -> kubelet::pod::Pod {
+        pod(json!({"runtimeClassName": "gvisor"}))
+    }
+
+    fn unusable(result: anyhow::Result<RuntimeClassConfig>) -> Unusable {
+        result.unwrap_err().downcast::<Unusable>().unwrap()
+    }
+
+    #[tokio::test]
+    async fn uses_the_default_handler_without_a_runtime_class() {
+        let client = api_server(vec![]);
+        let pod = pod(json!({}));
+        let config = resolve(client, "node-1", &pod).await.unwrap();
+        assert_eq!(config, RuntimeClassConfig::default());
+    }
+
+    #[tokio::test]
+    async fn resolves_the_handler() {
+        let client = api_server(vec![(
+            "/apis/node.k8s.io/v1beta1/runtimeclasses/gvisor",
+            200,
+            gvisor(json!(null)),
+        )]);
+        let config = resolve(client, "node-1", &gvisor_pod()).await.unwrap();
+        assert_eq!(config.handler, "runsc");
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_runtime_classes() {
+        let client = api_server(vec![]);
+        let result = resolve(client, "node-1", &gvisor_pod()).await;
+        assert_eq!(
+            unusable(result),
+            Unusable::NotFound {
+                name: "gvisor".to_string()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn checks_the_node_selector() {
+        let client = api_server(vec![
+            (
+                "/apis/node.k8s.io/v1beta1/runtimeclasses/gvisor",
+                200,
+                gvisor(json!({"nodeSelector": {"sandbox": "gvisor"}})),
+            ),
+            ("/api/v1/nodes/node-1", 200, node()),
+        ]);
+        let config = resolve(client, "node-1", &gvisor_pod()).await.unwrap();
+        assert_eq!(config.handler, "runsc");
+
+        let client = api_server(vec![
+            (
+                "/apis/node.k8s.io/v1beta1/runtimeclasses/gvisor",
+                200,
+                gvisor(json!({"nodeSelector": {"sandbox": "kata"}})),
+            ),
+            ("/api/v1/nodes/node-1", 200, node()),
+        ]);
+        let result = resolve(client, "node-1", &gvisor_pod()).await;
+        assert_eq!(
+            unusable(result),
+            Unusable::NodeSelector {
+                name: "gvisor".to_string(),
+                key: "sandbox".to_string(),
+                value: "kata".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_transient() {
+        let client = api_server(vec![(
+            "/apis/node.k8s.io/v1beta1/runtimeclasses/gvisor",
+            503,
+            json!({
+                "kind": "Status",
+                "apiVersion": "v1",
+                "status": "Failure",
+                "message": "etcd unavailable",
+                "reason": "ServiceUnavailable",
+                "code": 503,
+            }),
+        )]);
+        let error = resolve(client, "node-1", &gvisor_pod()).await.unwrap_err();
+        assert!(error.downcast_ref::<Unusable>().is_none());
+    }
+}
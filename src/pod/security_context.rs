@@ -0,0 +1,818 @@
+use k8s_cri::v1alpha2 as cri;
+use k8s_openapi::api::core::v1::{
+    Capabilities, Container, Pod as KubePod, PodSecurityContext, SELinuxOptions,
+};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::env::ContainerConfigError;
+
+/// Sysctls which are isolated per pod and cannot affect the node or other pods.
+const SAFE_SYSCTLS: &[&str] = &[
+    "kernel.shm_rmid_forced",
+    "net.ipv4.ip_local_port_range",
+    "net.ipv4.tcp_syncookies",
+    "net.ipv4.ping_group_range",
+];
+
+const SECCOMP_POD_ANNOTATION: &str = "seccomp.security.alpha.kubernetes.io/pod";
+const SECCOMP_CONTAINER_ANNOTATION_PREFIX: &str = "container.seccomp.security.alpha.kubernetes.io/";
+const APPARMOR_CONTAINER_ANNOTATION_PREFIX: &str =
+    "container.apparmor.security.beta.kubernetes.io/";
+
+const RUNTIME_DEFAULT_PROFILE: &str = "runtime/default";
+const DOCKER_DEFAULT_PROFILE: &str = "docker/default";
+const UNCONFINED_PROFILE: &str = "unconfined";
+const LOCALHOST_PROFILE_PREFIX: &str = "localhost/";
+
+const APPARMOR_ENABLED_PATH: &str = "/sys/module/apparmor/parameters/enabled";
+const APPARMOR_PROFILES_PATH: &str = "/sys/kernel/security/apparmor/profiles";
+
+/// Paths under `/proc` and `/sys` hidden from containers unless `procMount` is `Unmasked`.
+const DEFAULT_MASKED_PATHS: &[&str] = &[
+    "/proc/acpi",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/sys/firmware",
+];
+
+/// Paths under `/proc` made read-only unless `procMount` is `Unmasked`.
+const DEFAULT_READONLY_PATHS: &[&str] = &[
+    "/proc/asound",
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+
+const UNMASKED_PROC_MOUNT: &str = "Unmasked";
+
+/// The user an image runs as when neither the container nor the pod chooses one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImageUser {
+    Uid(i64),
+    Username(String),
+}
+
+impl ImageUser {
+    /// Reads the user from an `ImageStatus` response. Images which do not set one run as root.
+    pub fn of(image: Option<&cri::Image>) -> Self {
+        match image {
+            Some(image) => match &image.uid {
+                Some(uid) => ImageUser::Uid(uid.value),
+                None if !image.username.is_empty() => ImageUser::Username(image.username.clone()),
+                None => ImageUser::Uid(0),
+            },
+            None => ImageUser::Uid(0),
+        }
+    }
+}
+
+/// Kernel namespace a sysctl is isolated by.
+#[derive(Debug, PartialEq)]
+enum SysctlNamespace {
+    Ipc,
+    Net,
+}
+
+fn pod_security_context(pod: &KubePod) -> PodSecurityContext {
+    pod.spec
+        .as_ref()
+        .and_then(|spec| spec.security_context.clone())
+        .unwrap_or_default()
+}
+
+/// Builds the sandbox security context from `spec.securityContext`.
+pub fn sandbox_security_context(
+    pod: &KubePod,
+    namespace_options: cri::NamespaceOption,
+    seccomp_root: &Path,
+) -> cri::LinuxSandboxSecurityContext {
+    let security_context = pod_security_context(pod);
+    cri::LinuxSandboxSecurityContext {
+        namespace_options: Some(namespace_options),
+        selinux_options: security_context
+            .se_linux_options
+            .as_ref()
+            .map(selinux_option),
+        run_as_user: security_context
+            .run_as_user
+            .map(|value| cri::Int64Value { value }),
+        run_as_group: security_context
+            .run_as_group
+            .map(|value| cri::Int64Value { value }),
+        supplemental_groups: supplemental_groups(&security_context),
+        seccomp_profile_path: seccomp_profile(pod, None, seccomp_root),
+        // Runtimes only allow privileged containers in privileged sandboxes.
+        privileged: has_privileged_container(pod),
+        ..Default::default()
+    }
+}
+
+fn has_privileged_container(pod: &KubePod) -> bool {
+    pod.spec
+        .iter()
+        .flat_map(|spec| {
+            spec.init_containers
+                .iter()
+                .flatten()
+                .chain(&spec.containers)
+        })
+        .any(|container| {
+            container
+                .security_context
+                .as_ref()
+                .and_then(|security_context| security_context.privileged)
+                .unwrap_or(false)
+        })
+}
+
+/// Builds a container's security context, defaulting to `spec.securityContext` where the
+/// container does not override it, and to the image's user where neither sets one.
+pub fn container_security_context(
+    pod: &KubePod,
+    container: &Container,
+    namespace_options: Option<cri::NamespaceOption>,
+    image_user: &ImageUser,
+    seccomp_root: &Path,
+) -> cri::LinuxContainerSecurityContext {
+    let pod_context = pod_security_context(pod);
+    let container_context = container.security_context.clone().unwrap_or_default();
+    let run_as_user = container_context.run_as_user.or(pod_context.run_as_user);
+    let (run_as_user, run_as_username) = match (run_as_user, image_user) {
+        (Some(uid), _) => (Some(uid), String::new()),
+        (None, ImageUser::Uid(uid)) => (Some(*uid), String::new()),
+        (None, ImageUser::Username(username)) => (None, username.clone()),
+    };
+    let (masked_paths, readonly_paths) =
+        if container_context.proc_mount.as_deref() == Some(UNMASKED_PROC_MOUNT) {
+            (vec![], vec![])
+        } else {
+            (
+                DEFAULT_MASKED_PATHS
+                    .iter()
+                    .map(|path| path.to_string())
+                    .collect(),
+                DEFAULT_READONLY_PATHS
+                    .iter()
+                    .map(|path| path.to_string())
+                    .collect(),
+            )
+        };
+    cri::LinuxContainerSecurityContext {
+        capabilities: container_context.capabilities.as_ref().map(capabilities),
+        privileged: container_context.privileged.unwrap_or(false),
+        namespace_options,
+        selinux_options: container_context
+            .se_linux_options
+            .as_ref()
+            .or(pod_context.se_linux_options.as_ref())
+            .map(selinux_option),
+        run_as_user: run_as_user.map(|value| cri::Int64Value { value }),
+        run_as_username,
+        run_as_group: container_context
+            .run_as_group
+            .or(pod_context.run_as_group)
+            .map(|value| cri::Int64Value { value }),
+        readonly_rootfs: container_context.read_only_root_filesystem.unwrap_or(false),
+        supplemental_groups: supplemental_groups(&pod_context),
+        seccomp_profile_path: seccomp_profile(pod, Some(&container.name), seccomp_root),
+        apparmor_profile: apparmor_profile(pod, &container.name)
+            .cloned()
+            .unwrap_or_default(),
+        // Escalation is allowed unless explicitly disabled.
+        no_new_privs: container_context.allow_privilege_escalation == Some(false),
+        masked_paths,
+        readonly_paths,
+    }
+}
+
+fn capabilities(capabilities: &Capabilities) -> cri::Capability {
+    cri::Capability {
+        add_capabilities: capabilities.add.clone().unwrap_or_default(),
+        drop_capabilities: capabilities.drop.clone().unwrap_or_default(),
+    }
+}
+
+/// Checks that a container with `runAsNonRoot` cannot run as root, either through its own or
+/// the pod's `runAsUser` or through its image's user. Only numeric image users can be verified.
+pub fn verify_run_as_non_root(
+    pod: &KubePod,
+    container: &Container,
+    image_user: &ImageUser,
+) -> anyhow::Result<()> {
+    let pod_context = pod_security_context(pod);
+    let container_context = container.security_context.clone().unwrap_or_default();
+    if !container_context
+        .run_as_non_root
+        .or(pod_context.run_as_non_root)
+        .unwrap_or(false)
+    {
+        return Ok(());
+    }
+    let pod_name = pod.metadata.name.as_deref().unwrap_or_default();
+    match container_context.run_as_user.or(pod_context.run_as_user) {
+        Some(0) => anyhow::bail!(ContainerConfigError(format!(
+            "container's runAsUser breaks non-root policy (pod: {:?}, container: {})",
+            pod_name, &container.name
+        ))),
+        Some(_) => Ok(()),
+        None => match image_user {
+            ImageUser::Uid(0) => anyhow::bail!(ContainerConfigError(format!(
+                "container has runAsNonRoot and image will run as root (pod: {:?}, container: {})",
+                pod_name, &container.name
+            ))),
+            ImageUser::Uid(_) => Ok(()),
+            ImageUser::Username(username) => anyhow::bail!(ContainerConfigError(format!(
+                "container has runAsNonRoot and image has non-numeric user ({}), cannot verify user is non-root (pod: {:?}, container: {})",
+                username, pod_name, &container.name
+            ))),
+        },
+    }
+}
+
+/// Returns `supplementalGroups`, plus `fsGroup` so that containers can use volumes owned by it.
+fn supplemental_groups(security_context: &PodSecurityContext) -> Vec<i64> {
+    let mut groups = security_context
+        .supplemental_groups
+        .clone()
+        .unwrap_or_default();
+    if let Some(fs_group) = security_context.fs_group {
+        if !groups.contains(&fs_group) {
+            groups.push(fs_group);
+        }
+    }
+    groups
+}
+
+fn selinux_option(options: &SELinuxOptions) -> cri::SeLinuxOption {
+    cri::SeLinuxOption {
+        user: options.user.clone().unwrap_or_default(),
+        role: options.role.clone().unwrap_or_default(),
+        r#type: options.type_.clone().unwrap_or_default(),
+        level: options.level.clone().unwrap_or_default(),
+    }
+}
+
+/// Returns the seccomp annotation for a container, falling back to the pod's, or the pod's
+/// alone when `container` is `None`.
+fn seccomp_annotation<'a>(pod: &'a KubePod, container: Option<&str>) -> Option<&'a String> {
+    let annotations = pod.metadata.annotations.as_ref()?;
+    container
+        .and_then(|container| {
+            annotations.get(&format!(
+                "{}{}",
+                SECCOMP_CONTAINER_ANNOTATION_PREFIX, container
+            ))
+        })
+        .or_else(|| annotations.get(SECCOMP_POD_ANNOTATION))
+}
+
+/// Returns the seccomp profile set by annotation for a container, or for the whole pod when
+/// `container` is `None`. `localhost/` profiles are resolved against `seccomp_root`. An empty
+/// profile leaves the container unconfined.
+///
+/// Only the annotations are read. The `seccompProfile` fields of `securityContext` were added
+/// in Kubernetes 1.19 and are not available in the v1.17 API this crate is built against.
+pub fn seccomp_profile(pod: &KubePod, container: Option<&str>, seccomp_root: &Path) -> String {
+    match seccomp_annotation(pod, container) {
+        Some(profile) if profile.starts_with(LOCALHOST_PROFILE_PREFIX) => format!(
+            "{}{}",
+            LOCALHOST_PROFILE_PREFIX,
+            seccomp_root
+                .join(&profile[LOCALHOST_PROFILE_PREFIX.len()..])
+                .display()
+        ),
+        Some(profile) => profile.clone(),
+        None => String::new(),
+    }
+}
+
+/// Returns the AppArmor annotation for a container. Without one, the runtime applies its
+/// default profile. As with seccomp, the later `appArmorProfile` fields are not available.
+fn apparmor_profile<'a>(pod: &'a KubePod, container: &str) -> Option<&'a String> {
+    pod.metadata.annotations.as_ref()?.get(&format!(
+        "{}{}",
+        APPARMOR_CONTAINER_ANNOTATION_PREFIX, container
+    ))
+}
+
+/// Returns the pod's init and regular containers.
+fn all_containers(pod: &KubePod) -> Vec<&Container> {
+    pod.spec
+        .iter()
+        .flat_map(|spec| {
+            spec.init_containers
+                .iter()
+                .flatten()
+                .chain(&spec.containers)
+        })
+        .collect()
+}
+
+/// Checks that the seccomp profiles requested for a pod are valid, and that `localhost/`
+/// profiles exist under `seccomp_root`.
+pub async fn check_seccomp_profiles(pod: &KubePod, seccomp_root: &Path) -> anyhow::Result<()> {
+    // Collected up front, as borrowing iterators must not be held across the awaits below.
+    let profiles: Vec<String> = std::iter::once(seccomp_annotation(pod, None))
+        .chain(
+            all_containers(pod)
+                .into_iter()
+                .map(|container| seccomp_annotation(pod, Some(&container.name))),
+        )
+        .flatten()
+        .cloned()
+        .collect();
+    for profile in profiles {
+        match profile.as_str() {
+            RUNTIME_DEFAULT_PROFILE | DOCKER_DEFAULT_PROFILE | UNCONFINED_PROFILE => (),
+            _ if profile.starts_with(LOCALHOST_PROFILE_PREFIX) => {
+                let name = &profile[LOCALHOST_PROFILE_PREFIX.len()..];
+                if !is_descending_path(name) {
+                    anyhow::bail!("Invalid seccomp profile {}.", profile);
+                }
+                let path = seccomp_root.join(name);
+                if tokio::fs::metadata(&path).await.is_err() {
+                    anyhow::bail!("Seccomp profile {} not found.", path.display());
+                }
+            }
+            _ => anyhow::bail!("Invalid seccomp profile {}.", profile),
+        }
+    }
+    Ok(())
+}
+
+/// Checks that AppArmor is enabled if any container requests a profile, and that
+/// `localhost/` profiles are loaded.
+pub async fn check_apparmor_profiles(pod: &KubePod) -> anyhow::Result<()> {
+    let profiles: Vec<String> = all_containers(pod)
+        .into_iter()
+        .filter_map(|container| apparmor_profile(pod, &container.name))
+        .filter(|profile| profile.as_str() != UNCONFINED_PROFILE)
+        .cloned()
+        .collect();
+    if profiles.is_empty() {
+        return Ok(());
+    }
+    let enabled = tokio::fs::read_to_string(APPARMOR_ENABLED_PATH)
+        .await
+        .map(|enabled| enabled.starts_with('Y'))
+        .unwrap_or(false);
+    if !enabled {
+        anyhow::bail!("Cannot enforce AppArmor: AppArmor is not enabled on the host.");
+    }
+    let loaded = tokio::fs::read_to_string(APPARMOR_PROFILES_PATH)
+        .await
+        .unwrap_or_default();
+    for profile in profiles {
+        if profile == RUNTIME_DEFAULT_PROFILE {
+            continue;
+        }
+        if !profile.starts_with(LOCALHOST_PROFILE_PREFIX) {
+            anyhow::bail!("Cannot enforce AppArmor: invalid profile {}.", profile);
+        }
+        let name = &profile[LOCALHOST_PROFILE_PREFIX.len()..];
+        // Each line is a profile name followed by its mode, such as "name (enforce)".
+        let is_loaded = loaded
+            .lines()
+            .any(|line| line.rsplit_once(" (").map(|(loaded, _)| loaded) == Some(name));
+        if !is_loaded {
+            anyhow::bail!("Cannot enforce AppArmor: profile {:?} is not loaded.", name);
+        }
+    }
+    Ok(())
+}
+
+/// Whether a path stays below the directory it is joined to.
+fn is_descending_path(path: &str) -> bool {
+    !path.is_empty()
+        && !Path::new(path).is_absolute()
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)))
+}
+
+/// Returns the sysctls requested by `spec.securityContext`.
+pub fn sysctls(pod: &KubePod) -> BTreeMap<String, String> {
+    pod_security_context(pod)
+        .sysctls
+        .unwrap_or_default()
+        .into_iter()
+        .map(|sysctl| (sysctl.name, sysctl.value))
+        .collect()
+}
+
+/// Checks that each requested sysctl is safe or explicitly allowed, and that it is isolated
+/// by a namespace the pod does not share with the node.
+pub fn check_sysctls(pod: &KubePod, allowed_unsafe: &[String]) -> anyhow::Result<()> {
+    let spec = pod.spec.clone().unwrap_or_default();
+    for name in sysctls(pod).keys() {
+        let allowed = SAFE_SYSCTLS.contains(&name.as_str())
+            || allowed_unsafe.iter().any(|pattern| {
+                if pattern.ends_with('*') {
+                    name.starts_with(&pattern[..pattern.len() - 1])
+                } else {
+                    pattern == name
+                }
+            });
+        if !allowed {
+            anyhow::bail!("Sysctl {} is not allowed on this node.", name);
+        }
+        match sysctl_namespace(name) {
+            Some(SysctlNamespace::Net) if spec.host_network.unwrap_or(false) => {
+                anyhow::bail!("Sysctl {} cannot be set with hostNetwork.", name)
+            }
+            Some(SysctlNamespace::Ipc) if spec.host_ipc.unwrap_or(false) => {
+                anyhow::bail!("Sysctl {} cannot be set with hostIPC.", name)
+            }
+            Some(_) => (),
+            None => anyhow::bail!("Sysctl {} is not namespaced.", name),
+        }
+    }
+    Ok(())
+}
+
+fn sysctl_namespace(name: &str) -> Option<SysctlNamespace> {
+    if name == "kernel.sem"
+        || name.starts_with("kernel.shm")
+        || name.starts_with("kernel.msg")
+        || name.starts_with("fs.mqueue.")
+    {
+        Some(SysctlNamespace::Ipc)
+    } else if name.starts_with("net.") {
+        Some(SysctlNamespace::Net)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::kube_pod;
+    use serde_json::json;
+
+    fn pod_with_sysctls(names: &[&str], host: serde_json::Value) -> KubePod {
+        let sysctls: Vec<_> = names
+            .iter()
+            .map(|name| json!({ "name": name, "value": "1" }))
+            .collect();
+        let mut spec = json!({ "securityContext": { "sysctls": sysctls } });
+        for (key, value) in host.as_object().unwrap() {
+            spec[key] = value.clone();
+        }
+        kube_pod(spec)
+    }
+
+    #[test]
+    fn allows_safe_and_allowed_sysctls() {
+        let pod = pod_with_sysctls(
+            &[
+                "net.ipv4.tcp_syncookies",
+                "kernel.msgmax",
+                "net.core.somaxconn",
+            ],
+            json!({}),
+        );
+        let allowed = vec!["kernel.msg*".to_string(), "net.core.somaxconn".to_string()];
+        check_sysctls(&pod, &allowed).unwrap();
+        assert!(check_sysctls(&pod, &[]).is_err());
+    }
+
+    #[test]
+    fn rejects_sysctls_shared_with_the_node() {
+        let allowed = vec!["*".to_string()];
+        let pod = pod_with_sysctls(&["net.core.somaxconn"], json!({ "hostNetwork": true }));
+        assert!(check_sysctls(&pod, &allowed).is_err());
+        let pod = pod_with_sysctls(&["kernel.shmmax"], json!({ "hostIPC": true }));
+        assert!(check_sysctls(&pod, &allowed).is_err());
+        let pod = pod_with_sysctls(&["vm.swappiness"], json!({}));
+        assert!(check_sysctls(&pod, &allowed).is_err());
+    }
+
+    #[test]
+    fn adds_the_fs_group_to_supplemental_groups() {
+        let security_context: PodSecurityContext =
+            serde_json::from_value(json!({ "supplementalGroups": [5, 10], "fsGroup": 10 }))
+                .unwrap();
+        assert_eq!(supplemental_groups(&security_context), vec![5, 10]);
+        let security_context: PodSecurityContext =
+            serde_json::from_value(json!({ "fsGroup": 2000 })).unwrap();
+        assert_eq!(supplemental_groups(&security_context), vec![2000]);
+    }
+
+    #[test]
+    fn prefers_the_container_seccomp_profile() {
+        let root = Path::new("/var/lib/kubelet/seccomp");
+        let mut pod = kube_pod(json!({}));
+        assert_eq!(seccomp_profile(&pod, None, root), "");
+        pod.metadata.annotations = Some(
+            vec![
+                (
+                    SECCOMP_POD_ANNOTATION.to_string(),
+                    "runtime/default".to_string(),
+                ),
+                (
+                    format!("{}app", SECCOMP_CONTAINER_ANNOTATION_PREFIX),
+                    "unconfined".to_string(),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        assert_eq!(seccomp_profile(&pod, None, root), "runtime/default");
+        assert_eq!(seccomp_profile(&pod, Some("app"), root), "unconfined");
+        assert_eq!(
+            seccomp_profile(&pod, Some("sidecar"), root),
+            "runtime/default"
+        );
+    }
+
+    /// A pod whose `app` container has the given security context.
+    fn pod_with_contexts(pod_context: serde_json::Value, context: serde_json::Value) -> KubePod {
+        kube_pod(json!({
+            "securityContext": pod_context,
+            "containers": [{ "name": "app", "securityContext": context }],
+        }))
+    }
+
+    fn app(pod: &KubePod) -> &Container {
+        &pod.spec.as_ref().unwrap().containers[0]
+    }
+
+    #[test]
+    fn builds_the_sandbox_context_from_the_pod() {
+        let pod = kube_pod(json!({
+            "securityContext": {
+                "runAsUser": 1000,
+                "runAsGroup": 3000,
+                "supplementalGroups": [5],
+                "fsGroup": 2000,
+                "seLinuxOptions": { "level": "s0:c123,c456" },
+            },
+            "containers": [
+                { "name": "app" },
+                { "name": "sidecar", "securityContext": { "privileged": true } },
+            ],
+        }));
+        let context =
+            sandbox_security_context(&pod, cri::NamespaceOption::default(), Path::new(""));
+        assert_eq!(context.run_as_user, Some(cri::Int64Value { value: 1000 }));
+        assert_eq!(context.run_as_group, Some(cri::Int64Value { value: 3000 }));
+        assert_eq!(context.supplemental_groups, vec![5, 2000]);
+        assert_eq!(context.selinux_options.unwrap().level, "s0:c123,c456");
+        assert!(context.namespace_options.is_some());
+        assert!(context.privileged);
+
+        let context =
+            sandbox_security_context(&kube_pod(json!({})), Default::default(), Path::new(""));
+        assert_eq!(context.run_as_user, None);
+        assert!(!context.privileged);
+    }
+
+    #[test]
+    fn inherits_the_pod_context() {
+        let pod = pod_with_contexts(
+            json!({
+                "runAsUser": 1000,
+                "runAsGroup": 3000,
+                "seLinuxOptions": { "level": "s0:c1" },
+            }),
+            json!({ "runAsUser": 2000 }),
+        );
+        let context =
+            container_security_context(&pod, app(&pod), None, &ImageUser::Uid(0), Path::new(""));
+        assert_eq!(context.run_as_user, Some(cri::Int64Value { value: 2000 }));
+        assert_eq!(context.run_as_group, Some(cri::Int64Value { value: 3000 }));
+        assert_eq!(context.selinux_options.unwrap().level, "s0:c1");
+    }
+
+    #[test]
+    fn defaults_to_the_image_user() {
+        let pod = pod_with_contexts(json!({}), json!({}));
+        let image_user = ImageUser::Username("nobody".to_string());
+        let context = container_security_context(&pod, app(&pod), None, &image_user, Path::new(""));
+        assert_eq!(context.run_as_user, None);
+        assert_eq!(context.run_as_username, "nobody");
+        let context = container_security_context(
+            &pod,
+            app(&pod),
+            None,
+            &ImageUser::Uid(65534),
+            Path::new(""),
+        );
+        assert_eq!(context.run_as_user, Some(cri::Int64Value { value: 65534 }));
+        assert_eq!(context.run_as_username, "");
+    }
+
+    #[test]
+    fn maps_capabilities_and_privileges() {
+        let pod = pod_with_contexts(
+            json!({}),
+            json!({
+                "capabilities": { "add": ["NET_ADMIN"], "drop": ["ALL"] },
+                "privileged": true,
+                "readOnlyRootFilesystem": true,
+                "allowPrivilegeEscalation": false,
+            }),
+        );
+        let context =
+            container_security_context(&pod, app(&pod), None, &ImageUser::Uid(0), Path::new(""));
+        assert_eq!(
+            context.capabilities,
+            Some(cri::Capability {
+                add_capabilities: vec!["NET_ADMIN".to_string()],
+                drop_capabilities: vec!["ALL".to_string()],
+            })
+        );
+        assert!(context.privileged);
+        assert!(context.readonly_rootfs);
+        assert!(context.no_new_privs);
+
+        // Escalation is allowed unless explicitly disabled.
+        for allow in &[json!(null), json!(true)] {
+            let pod = pod_with_contexts(json!({}), json!({ "allowPrivilegeEscalation": allow }));
+            let context = container_security_context(
+                &pod,
+                app(&pod),
+                None,
+                &ImageUser::Uid(0),
+                Path::new(""),
+            );
+            assert!(!context.no_new_privs);
+            assert_eq!(context.capabilities, None);
+            assert!(!context.privileged);
+        }
+    }
+
+    #[test]
+    fn unmasks_proc_only_when_asked() {
+        let pod = pod_with_contexts(json!({}), json!({}));
+        let context =
+            container_security_context(&pod, app(&pod), None, &ImageUser::Uid(0), Path::new(""));
+        assert!(context.masked_paths.contains(&"/proc/kcore".to_string()));
+        assert!(context.readonly_paths.contains(&"/proc/sys".to_string()));
+
+        let pod = pod_with_contexts(json!({}), json!({ "procMount": "Unmasked" }));
+        let context =
+            container_security_context(&pod, app(&pod), None, &ImageUser::Uid(0), Path::new(""));
+        assert!(context.masked_paths.is_empty());
+        assert!(context.readonly_paths.is_empty());
+    }
+
+    #[test]
+    fn verifies_run_as_non_root() {
+        let is_config_error = |result: anyhow::Result<()>| {
+            result
+                .unwrap_err()
+                .downcast_ref::<ContainerConfigError>()
+                .is_some()
+        };
+        let root = ImageUser::Uid(0);
+        let named = ImageUser::Username("app".to_string());
+
+        // Nothing to verify without runAsNonRoot.
+        let pod = pod_with_contexts(json!({}), json!({}));
+        verify_run_as_non_root(&pod, app(&pod), &root).unwrap();
+
+        let pod = pod_with_contexts(json!({ "runAsNonRoot": true }), json!({}));
+        assert!(is_config_error(verify_run_as_non_root(
+            &pod,
+            app(&pod),
+            &root
+        )));
+        assert!(is_config_error(verify_run_as_non_root(
+            &pod,
+            app(&pod),
+            &named
+        )));
+        verify_run_as_non_root(&pod, app(&pod), &ImageUser::Uid(1000)).unwrap();
+
+        // An explicit runAsUser takes precedence over the image user.
+        let pod = pod_with_contexts(
+            json!({ "runAsNonRoot": true }),
+            json!({ "runAsUser": 1000 }),
+        );
+        verify_run_as_non_root(&pod, app(&pod), &root).unwrap();
+        verify_run_as_non_root(&pod, app(&pod), &named).unwrap();
+        let pod = pod_with_contexts(json!({ "runAsUser": 0 }), json!({ "runAsNonRoot": true }));
+        assert!(is_config_error(verify_run_as_non_root(
+            &pod,
+            app(&pod),
+            &ImageUser::Uid(1000)
+        )));
+    }
+
+    #[test]
+    fn reads_the_image_user() {
+        assert_eq!(ImageUser::of(None), ImageUser::Uid(0));
+        let image = cri::Image {
+            uid: Some(cri::Int64Value { value: 1000 }),
+            ..Default::default()
+        };
+        assert_eq!(ImageUser::of(Some(&image)), ImageUser::Uid(1000));
+        let image = cri::Image {
+            username: "nobody".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            ImageUser::of(Some(&image)),
+            ImageUser::Username("nobody".to_string())
+        );
+        assert_eq!(
+            ImageUser::of(Some(&cri::Image::default())),
+            ImageUser::Uid(0)
+        );
+    }
+
+    fn annotated_pod(annotations: serde_json::Value) -> KubePod {
+        let mut pod = kube_pod(json!({}));
+        pod.metadata.annotations = serde_json::from_value(annotations).unwrap();
+        pod
+    }
+
+    /// A seccomp root unique to one test holding a single `audit.json` profile, removed when
+    /// dropped.
+    struct SeccompRoot(std::path::PathBuf);
+
+    impl SeccompRoot {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "krustlet-cri-{}-seccomp-{}",
+                std::process::id(),
+                name
+            ));
+            std::fs::create_dir_all(&root).unwrap();
+            std::fs::write(root.join("audit.json"), "{}").unwrap();
+            SeccompRoot(root)
+        }
+    }
+
+    impl Drop for SeccompRoot {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn resolves_localhost_seccomp_profiles() {
+        let root = Path::new("/var/lib/kubelet/seccomp");
+        let pod = annotated_pod(json!({
+            "seccomp.security.alpha.kubernetes.io/pod": "runtime/default",
+            "container.seccomp.security.alpha.kubernetes.io/app": "localhost/audit.json",
+        }));
+        assert_eq!(
+            seccomp_profile(&pod, Some("app"), root),
+            "localhost//var/lib/kubelet/seccomp/audit.json"
+        );
+        assert_eq!(
+            seccomp_profile(&pod, Some("other"), root),
+            "runtime/default"
+        );
+        assert_eq!(seccomp_profile(&pod, None, root), "runtime/default");
+        let unannotated = annotated_pod(json!({}));
+        assert_eq!(seccomp_profile(&unannotated, Some("app"), root), "");
+    }
+
+    #[tokio::test]
+    async fn accepts_existing_seccomp_profiles() {
+        let root = SeccompRoot::new("existing");
+        for profile in &["runtime/default", "unconfined", "localhost/audit.json"] {
+            let pod = annotated_pod(json!({ "seccomp.security.alpha.kubernetes.io/pod": profile }));
+            check_seccomp_profiles(&pod, &root.0).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_seccomp_profiles() {
+        let root = SeccompRoot::new("missing");
+        let pod = annotated_pod(json!({
+            "container.seccomp.security.alpha.kubernetes.io/app": "localhost/missing.json",
+        }));
+        let error = check_seccomp_profiles(&pod, &root.0).await.unwrap_err();
+        assert!(error.to_string().contains("not found"));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_seccomp_profiles() {
+        let root = SeccompRoot::new("invalid");
+        for profile in &["localhost/../audit.json", "localhost//etc/passwd", "other"] {
+            let pod = annotated_pod(json!({ "seccomp.security.alpha.kubernetes.io/pod": profile }));
+            assert!(check_seccomp_profiles(&pod, &root.0).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_apparmor_check_for_unconfined_containers() {
+        let pod = annotated_pod(json!({
+            "container.apparmor.security.beta.kubernetes.io/app": "unconfined",
+        }));
+        check_apparmor_profiles(&pod).await.unwrap();
+    }
+}
@@ -3,61 +3,94 @@ use k8s_cri::v1alpha2 as cri;
 use log::{debug, error, info};
 use std::sync::Arc;
 
+use crate::config::Config;
+use crate::node::capacity as node_capacity;
+use crate::node::info as node_info;
+use crate::pod::qos::QosClass;
+use crate::pod::{dns, labels, namespaces, ports, security_context};
+use crate::runtime::{Connection, Service, Watchdog};
 use crate::states::{PodState, Registered, SharedPodState, Terminated};
 
-type Namespace = String;
-type Pod = String;
+type Uid = String;
 type Container = String;
 type Id = String;
 
-pub(crate) type PodMap =
-    Arc<tokio::sync::RwLock<std::collections::HashMap<(Namespace, Pod), cri::PodSandbox>>>;
+/// Sandboxes keyed by pod UID, so that pods recreated with the same name are kept apart.
+pub(crate) type PodMap = Arc<tokio::sync::RwLock<std::collections::HashMap<Uid, cri::PodSandbox>>>;
 pub(crate) type ContainerMap =
     Arc<tokio::sync::RwLock<std::collections::HashMap<(Id, Container), cri::Container>>>;
 
 pub struct Provider {
     shared: SharedPodState,
+    max_pods: u16,
 }
 
 impl Provider {
-    pub fn new_from_socket_address(socket_address: &'static str, kubeconfig: kube::Config) -> Self {
+    /// Creates the provider and starts watching runtime health in the background.
+    pub fn new(
+        config: Config,
+        kubeconfig: kube::Config,
+        kubelet_config: &kubelet::config::Config,
+    ) -> Self {
+        let node_name = kubelet_config.node_name.clone();
+        let runtime = Connection::new(
+            config.runtime_endpoint.clone(),
+            Service::Runtime,
+            config.timeouts,
+        );
+        let image = if config.image_endpoint == config.runtime_endpoint {
+            runtime.clone()
+        } else {
+            Connection::new(
+                config.image_endpoint.clone(),
+                Service::Image,
+                config.timeouts,
+            )
+        };
+        let seccomp_root = config
+            .seccomp_profile_root
+            .clone()
+            .unwrap_or_else(|| kubelet_config.data_dir.join("seccomp"));
+        let health = Watchdog::default();
+        tokio::spawn(
+            health
+                .clone()
+                .run(runtime.clone(), kubeconfig.clone(), node_name.clone()),
+        );
         Provider {
             shared: SharedPodState {
-                socket_address,
+                config: Arc::new(config),
+                runtime,
+                image,
+                health,
                 kubeconfig,
+                node_name,
+                host_ports: Default::default(),
+                host_ip: kubelet_config.node_ip.to_string(),
+                pods_dir: kubelet_config.data_dir.join("pods"),
+                seccomp_root,
+                allocatable: Default::default(),
                 pods: Arc::new(tokio::sync::RwLock::new(std::collections::HashMap::new())),
                 containers: Arc::new(tokio::sync::RwLock::new(std::collections::HashMap::new())),
             },
+            max_pods: kubelet_config.max_pods,
         }
     }
 
+    /// The runtime watchdog, which owns the node's conditions.
+    pub fn health(&self) -> Watchdog {
+        self.shared.health.clone()
+    }
+
+    /// Returns the sandbox of the newest generation of a pod with this name.
     async fn pod_id(&self, namespace: &str, pod: &str) -> anyhow::Result<Id> {
-        let key = (namespace.to_string(), pod.to_string());
-        let has_pod = self.shared.pods.read().await.contains_key(&key);
-        if has_pod {
-            Ok(self
-                .shared
-                .pods
-                .read()
-                .await
-                .get(&key)
-                .unwrap()
-                .id
-                .to_string())
-        } else {
-            self.shared.refresh_pods().await?;
-            let has_pod = self.shared.pods.read().await.contains_key(&key);
-            if has_pod {
-                Ok(self
-                    .shared
-                    .pods
-                    .read()
-                    .await
-                    .get(&key)
-                    .unwrap()
-                    .id
-                    .to_string())
-            } else {
+        if let Some(sandbox) = self.shared.newest_pod(namespace, pod).await {
+            return Ok(sandbox.id);
+        }
+        self.shared.refresh_pods().await?;
+        match self.shared.newest_pod(namespace, pod).await {
+            Some(sandbox) => Ok(sandbox.id),
+            None => {
                 error!("Could not find namespace {} pod {}.", namespace, pod);
                 anyhow::bail!(kubelet::provider::ProviderError::PodNotFound {
                     pod_name: pod.to_string(),
@@ -127,13 +160,7 @@ impl Provider {
                 anyhow::bail!(e);
             }
         };
-        let response = match client.container_status(request).await {
-            Ok(response) => response.into_inner(),
-            Err(e) => {
-                error!("Error making request: {:?}", &e);
-                anyhow::bail!(e);
-            }
-        };
+        let response = client.container_status(request).await?;
         debug!("{:?}", &response);
 
         if let Some(status) = response.status {
@@ -147,8 +174,6 @@ impl Provider {
     }
 }
 
-const AMD64: &str = "amd64";
-
 #[async_trait]
 impl kubelet::provider::Provider for Provider {
     type PodState = PodState;
@@ -156,43 +181,67 @@ impl kubelet::provider::Provider for Provider {
     type InitialState = Registered;
     type TerminatedState = Terminated;
 
-    const ARCH: &'static str = AMD64;
+    const ARCH: &'static str = node_info::ARCH;
 
     async fn initialize_pod_state(
         &self,
         pod: &kubelet::pod::Pod,
     ) -> anyhow::Result<Self::PodState> {
+        let uid = pod
+            .as_kube_pod()
+            .metadata
+            .uid
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Pod {} has no UID.", pod.name()))?;
+        let log_directory = format!("/var/log/pods/{}_{}_{}/", pod.namespace(), pod.name(), &uid);
         let metadata = Some(cri::PodSandboxMetadata {
             name: pod.name().to_string(),
             namespace: pod.namespace().to_string(),
-            uid: "".to_string(),
+            uid: uid.clone(),
             attempt: 0,
         });
 
-        let hostname = pod
-            .as_kube_pod()
-            .spec
-            .clone()
-            .unwrap_or_default()
-            .hostname
-            .unwrap_or_else(|| "".to_string());
+        let hostname = namespaces::hostname(pod.as_kube_pod());
 
-        let log_directory = format!("/var/log/pods/{}/{}/", pod.namespace(), pod.name());
+        let mut rejected = None;
 
-        // TODO
-        let dns_config = Some(cri::DnsConfig {
-            servers: vec![],
-            searches: vec![],
-            options: vec![],
-        });
+        let dns_config = match dns::dns_config(pod.as_kube_pod(), &self.shared.config).await {
+            Ok(dns_config) => Some(dns_config),
+            Err(e) => {
+                rejected = Some(format!("Invalid DNS configuration: {}", e));
+                None
+            }
+        };
 
-        let port_mappings = vec![];
+        let port_mappings = match ports::port_mappings(pod.as_kube_pod()) {
+            Ok(port_mappings) => port_mappings,
+            Err(e) => {
+                rejected = Some(format!("Invalid port mappings: {}", e));
+                vec![]
+            }
+        };
 
-        let labels = pod.labels().clone();
+        let labels = labels::sandbox_labels(pod.as_kube_pod());
 
         let annotations = pod.annotations().clone();
 
-        let linux = None;
+        let qos_class = match QosClass::of(pod.as_kube_pod()) {
+            Ok(qos_class) => qos_class,
+            Err(e) => {
+                rejected = Some(format!("Invalid resources: {}", e));
+                QosClass::default()
+            }
+        };
+
+        let linux = Some(cri::LinuxPodSandboxConfig {
+            cgroup_parent: qos_class.cgroup_parent(&uid, self.shared.config.cgroup_driver),
+            security_context: Some(security_context::sandbox_security_context(
+                pod.as_kube_pod(),
+                namespaces::namespace_options(pod.as_kube_pod()),
+                &self.shared.seccomp_root,
+            )),
+            sysctls: security_context::sysctls(pod.as_kube_pod()),
+        });
 
         let sandbox_config = cri::PodSandboxConfig {
             metadata,
@@ -208,14 +257,37 @@ impl kubelet::provider::Provider for Provider {
         Ok(PodState {
             shared: self.shared.clone(),
             sandbox_config,
+            runtime_class: Default::default(),
+            qos_class,
+            sandbox_id: None,
+            pod_ips: vec![],
+            rejected,
         })
     }
 
     async fn node(&self, builder: &mut kubelet::node::Builder) -> anyhow::Result<()> {
-        let request = tonic::Request::new(cri::VersionRequest {
-            version: "v1alpha2".to_string(),
-        });
-        debug!("Sending request: {:?}", &request);
+        builder.add_annotation(
+            "kubeadm.alpha.kubernetes.io/cri-socket",
+            &self.shared.runtime.endpoint().to_string(),
+        );
+        // The Kubelet labels the node with `ARCH` and the OS itself.
+        builder.set_architecture(node_info::ARCH);
+
+        // Capacity is read from the host, so it is reported even if the runtime is down.
+        let capacity = node_capacity::detect(self.max_pods, &self.shared.image).await;
+        let allocatable = node_capacity::allocatable(&capacity, &self.shared.config)?;
+        info!(
+            "Node capacity: {:?}, allocatable: {:?}",
+            &capacity, &allocatable
+        );
+        for (name, value) in capacity {
+            builder.add_capacity(&name, &crate::quantity::format_millis(value).0);
+        }
+        for (name, value) in &allocatable {
+            builder.add_allocatable(name, &crate::quantity::format_millis(*value).0);
+        }
+        *self.shared.allocatable.write().await = allocatable;
+
         let mut client = match self.shared.client().await {
             Ok(client) => client,
             Err(e) => {
@@ -223,24 +295,20 @@ impl kubelet::provider::Provider for Provider {
                 anyhow::bail!(e);
             }
         };
-        let response = match client.version(request).await {
-            Ok(response) => response.into_inner(),
-            Err(e) => {
-                error!("Error making request: {:?}", &e);
-                anyhow::bail!(e);
-            }
-        };
-        info!("Found container runtime: {:?}", &response);
-
+        let request = tonic::Request::new(cri::VersionRequest {
+            version: client.api_version().as_str().to_string(),
+        });
+        debug!("Sending request: {:?}", &request);
+        let response = client.version(request).await?;
+        info!(
+            "Found container runtime using CRI {}: {:?}",
+            client.api_version().as_str(),
+            &response
+        );
         builder.set_container_runtime_version(&format!(
             "{}://{}",
             &response.runtime_name, &response.runtime_version
         ));
-        builder.add_annotation(
-            "kubeadm.alpha.kubernetes.io/cri-socket",
-            self.shared.socket_address,
-        );
-        builder.set_architecture("amd64");
         Ok(())
     }
 
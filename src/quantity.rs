@@ -0,0 +1,187 @@
+//! Conversion of Kubernetes resource quantities into integers.
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+
+/// Parses a quantity into thousandths of its unit, rounding up.
+///
+/// Accepts the formats produced by the API server: a decimal number followed by an optional
+/// binary suffix (`Ki`, `Mi`, `Gi`, `Ti`, `Pi`, `Ei`), decimal suffix (`n`, `u`, `m`, `k`,
+/// `M`, `G`, `T`, `P`, `E`) or decimal exponent (`e3`, `E-2`).
+pub fn parse_millis(quantity: &str) -> anyhow::Result<i128> {
+    let quantity = quantity.trim();
+    let number_end = quantity
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '+' || c == '-'))
+        .unwrap_or(quantity.len());
+    let (number, suffix) = quantity.split_at(number_end);
+
+    let (negative, number) = if let Some(number) = number.strip_prefix('-') {
+        (true, number)
+    } else if let Some(number) = number.strip_prefix('+') {
+        (false, number)
+    } else {
+        (false, number)
+    };
+    let mut parts = number.splitn(2, '.');
+    let whole = parts.next().unwrap_or("");
+    let fraction = parts.next().unwrap_or("");
+    if (whole.is_empty() && fraction.is_empty())
+        || !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        anyhow::bail!("Invalid quantity {:?}.", quantity);
+    }
+
+    let mut digits: i128 = 0;
+    for c in whole.chars().chain(fraction.chars()) {
+        digits = digits
+            .checked_mul(10)
+            .and_then(|digits| digits.checked_add(i128::from(c as u8 - b'0')))
+            .ok_or_else(|| anyhow::anyhow!("Quantity {:?} is too large.", quantity))?;
+    }
+
+    let (binary_exponent, decimal_exponent) = parse_suffix(suffix)
+        .ok_or_else(|| anyhow::anyhow!("Invalid quantity suffix in {:?}.", quantity))?;
+    // The number is `digits * 10^-fraction.len()`, expressed here in thousandths.
+    let exponent = decimal_exponent + 3 - fraction.len() as i32;
+
+    let overflow = || anyhow::anyhow!("Quantity {:?} is too large.", quantity);
+    let mut value = digits
+        .checked_mul(2i128.checked_pow(binary_exponent).ok_or_else(overflow)?)
+        .ok_or_else(overflow)?;
+    if exponent >= 0 {
+        value = value
+            .checked_mul(10i128.checked_pow(exponent as u32).ok_or_else(overflow)?)
+            .ok_or_else(overflow)?;
+    } else {
+        let divisor = match 10i128.checked_pow((-exponent) as u32) {
+            Some(divisor) => divisor,
+            // Anything this small rounds up to a single thousandth.
+            None => return Ok(if digits == 0 || negative { 0 } else { 1 }),
+        };
+        value = if negative {
+            // Rounding up a negative value truncates towards zero.
+            value / divisor
+        } else {
+            (value + divisor - 1) / divisor
+        };
+    }
+    Ok(if negative { -value } else { value })
+}
+
+/// Returns the binary (power of two) and decimal (power of ten) exponents of a suffix.
+fn parse_suffix(suffix: &str) -> Option<(u32, i32)> {
+    let exponents = match suffix {
+        "" => (0, 0),
+        "Ki" => (10, 0),
+        "Mi" => (20, 0),
+        "Gi" => (30, 0),
+        "Ti" => (40, 0),
+        "Pi" => (50, 0),
+        "Ei" => (60, 0),
+        "n" => (0, -9),
+        "u" => (0, -6),
+        "m" => (0, -3),
+        "k" => (0, 3),
+        "M" => (0, 6),
+        "G" => (0, 9),
+        "T" => (0, 12),
+        "P" => (0, 15),
+        "E" => (0, 18),
+        _ if suffix.starts_with('e') || suffix.starts_with('E') => (0, suffix[1..].parse().ok()?),
+        _ => return None,
+    };
+    Some(exponents)
+}
+
+/// Returns a quantity in thousandths of its unit, rounding up.
+pub fn milli_value(quantity: &Quantity) -> anyhow::Result<i128> {
+    parse_millis(&quantity.0)
+}
+
+/// Returns a quantity in whole units, rounding up.
+pub fn value(quantity: &Quantity) -> anyhow::Result<i128> {
+    let millis = milli_value(quantity)?;
+    Ok(if millis > 0 {
+        (millis + 999) / 1000
+    } else {
+        millis / 1000
+    })
+}
+
+/// Formats thousandths of a unit as a canonical quantity.
+pub fn format_millis(millis: i128) -> Quantity {
+    if millis % 1000 == 0 {
+        Quantity((millis / 1000).to_string())
+    } else {
+        Quantity(format!("{}m", millis))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_plain_numbers() {
+        assert_eq!(parse_millis("0").unwrap(), 0);
+        assert_eq!(parse_millis("1").unwrap(), 1000);
+        assert_eq!(parse_millis("+2").unwrap(), 2000);
+        assert_eq!(parse_millis("-2").unwrap(), -2000);
+        assert_eq!(parse_millis("0.5").unwrap(), 500);
+        assert_eq!(parse_millis(".5").unwrap(), 500);
+        assert_eq!(parse_millis("1.").unwrap(), 1000);
+        assert_eq!(parse_millis(" 3 ").unwrap(), 3000);
+    }
+
+    #[test]
+    fn parses_suffixes() {
+        assert_eq!(parse_millis("100m").unwrap(), 100);
+        assert_eq!(parse_millis("1500m").unwrap(), 1500);
+        assert_eq!(parse_millis("1k").unwrap(), 1_000_000);
+        assert_eq!(parse_millis("1M").unwrap(), 1_000_000_000);
+        assert_eq!(parse_millis("1Ki").unwrap(), 1_024_000);
+        assert_eq!(parse_millis("256Mi").unwrap(), 256 * 1024 * 1024 * 1000);
+        assert_eq!(parse_millis("1.5Gi").unwrap(), 3 * 512 * 1024 * 1024 * 1000);
+        assert_eq!(parse_millis("1e3").unwrap(), 1_000_000);
+        assert_eq!(parse_millis("1E-3").unwrap(), 1);
+        assert_eq!(parse_millis("1Ei").unwrap(), (1i128 << 60) * 1000);
+    }
+
+    #[test]
+    fn rounds_up() {
+        assert_eq!(parse_millis("1u").unwrap(), 1);
+        assert_eq!(parse_millis("1n").unwrap(), 1);
+        assert_eq!(parse_millis("0.0001").unwrap(), 1);
+        assert_eq!(parse_millis("1500u").unwrap(), 2);
+        assert_eq!(parse_millis("-1500u").unwrap(), -1);
+        assert_eq!(parse_millis("1e-40").unwrap(), 1);
+        assert_eq!(parse_millis("0e-40").unwrap(), 0);
+    }
+
+    #[test]
+    fn converts_to_whole_units() {
+        assert_eq!(value(&Quantity("100m".to_string())).unwrap(), 1);
+        assert_eq!(value(&Quantity("128Mi".to_string())).unwrap(), 134_217_728);
+        assert_eq!(value(&Quantity("-100m".to_string())).unwrap(), 0);
+    }
+
+    #[test]
+    fn rejects_invalid_quantities() {
+        assert!(parse_millis("").is_err());
+        assert!(parse_millis("m").is_err());
+        assert!(parse_millis("1.2.3").is_err());
+        assert!(parse_millis("1Xi").is_err());
+        assert!(parse_millis("1 Mi").is_err());
+        assert!(parse_millis("--1").is_err());
+        assert!(parse_millis("1e").is_err());
+        assert!(parse_millis("1e100").is_err());
+    }
+
+    #[test]
+    fn formats_millis() {
+        assert_eq!(format_millis(2000).0, "2");
+        assert_eq!(format_millis(0).0, "0");
+        assert_eq!(format_millis(1500).0, "1500m");
+    }
+}
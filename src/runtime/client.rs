@@ -0,0 +1,304 @@
+use k8s_cri::v1alpha2 as cri;
+use log::{debug, error, warn};
+use tonic::client::Grpc;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::Channel;
+
+use super::{Connection, CriError, ErrorKind};
+
+/// CRI API versions spoken by KrustletCRI.
+///
+/// `runtime.v1` was introduced as a copy of `runtime.v1alpha2`, so the two are identical on the
+/// wire apart from their gRPC service paths. All pod spec translation is written once against the
+/// `v1alpha2` messages and sent over whichever version the runtime negotiated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApiVersion {
+    V1,
+    V1alpha2,
+}
+
+impl ApiVersion {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "v1",
+            ApiVersion::V1alpha2 => "v1alpha2",
+        }
+    }
+}
+
+/// Generates a unary method which routes to the service path of the negotiated API version.
+///
+/// Calls fail with `DeadlineExceeded` once the named timeout elapses, dropping the in-flight
+/// request. There is no separate cancellation: when a pod is deleted the Kubelet drops its state
+/// machine, and with it any call in flight, which resets the call's HTTP/2 stream.
+///
+/// Failures are returned as a [`CriError`] and reported to the connection, so that a lost
+/// channel is redialed by the next call.
+macro_rules! unary {
+    ($service:literal, $name:ident, $timeout:ident, $method:literal, $request:ty, $response:ty) => {
+        pub async fn $name(
+            &mut self,
+            request: impl tonic::IntoRequest<$request>,
+        ) -> Result<$response, CriError> {
+            let path = match self.version {
+                ApiVersion::V1 => {
+                    PathAndQuery::from_static(concat!("/runtime.v1.", $service, "/", $method))
+                }
+                ApiVersion::V1alpha2 => {
+                    PathAndQuery::from_static(concat!("/runtime.v1alpha2.", $service, "/", $method))
+                }
+            };
+            let timeout = self.connection.timeouts().$timeout;
+            let inner = &mut self.inner;
+            let call = async move {
+                inner.ready().await.map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e),
+                    )
+                })?;
+                inner
+                    .unary(request.into_request(), path, ProstCodec::default())
+                    .await
+            };
+            let result = tokio::time::timeout(timeout, call)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(tonic::Status::new(
+                        tonic::Code::DeadlineExceeded,
+                        format!("exceeded deadline of {:?}", timeout),
+                    ))
+                });
+            match result {
+                Ok(response) => Ok(response.into_inner()),
+                Err(status) => Err(failed(&self.connection, $method, status)),
+            }
+        }
+    };
+}
+
+/// Logs a failed call at a level matching its kind, and reports it to the connection.
+fn failed(connection: &Connection, method: &'static str, status: tonic::Status) -> CriError {
+    connection.report(&status);
+    let error = CriError::new(method, status);
+    match error.kind() {
+        ErrorKind::NotFound | ErrorKind::AlreadyExists => debug!("{}", &error),
+        ErrorKind::Retryable => warn!("{}", &error),
+        // Expected while negotiating the API version with older runtimes.
+        ErrorKind::Fatal if error.status().code() == tonic::Code::Unimplemented => {
+            debug!("{}", &error)
+        }
+        ErrorKind::Fatal => error!("{}", &error),
+    }
+    error
+}
+
+/// Client for the CRI `RuntimeService`.
+#[derive(Clone)]
+pub struct RuntimeClient {
+    inner: Grpc<Channel>,
+    version: ApiVersion,
+    connection: Connection,
+}
+
+impl RuntimeClient {
+    pub fn new(channel: Channel, version: ApiVersion, connection: Connection) -> Self {
+        RuntimeClient {
+            inner: Grpc::new(channel),
+            version,
+            connection,
+        }
+    }
+
+    pub fn api_version(&self) -> ApiVersion {
+        self.version
+    }
+
+    unary!(
+        "RuntimeService",
+        version,
+        status,
+        "Version",
+        cri::VersionRequest,
+        cri::VersionResponse
+    );
+    unary!(
+        "RuntimeService",
+        run_pod_sandbox,
+        runtime,
+        "RunPodSandbox",
+        cri::RunPodSandboxRequest,
+        cri::RunPodSandboxResponse
+    );
+    unary!(
+        "RuntimeService",
+        stop_pod_sandbox,
+        runtime,
+        "StopPodSandbox",
+        cri::StopPodSandboxRequest,
+        cri::StopPodSandboxResponse
+    );
+    unary!(
+        "RuntimeService",
+        remove_pod_sandbox,
+        runtime,
+        "RemovePodSandbox",
+        cri::RemovePodSandboxRequest,
+        cri::RemovePodSandboxResponse
+    );
+    unary!(
+        "RuntimeService",
+        pod_sandbox_status,
+        status,
+        "PodSandboxStatus",
+        cri::PodSandboxStatusRequest,
+        cri::PodSandboxStatusResponse
+    );
+    unary!(
+        "RuntimeService",
+        list_pod_sandbox,
+        status,
+        "ListPodSandbox",
+        cri::ListPodSandboxRequest,
+        cri::ListPodSandboxResponse
+    );
+    unary!(
+        "RuntimeService",
+        create_container,
+        runtime,
+        "CreateContainer",
+        cri::CreateContainerRequest,
+        cri::CreateContainerResponse
+    );
+    unary!(
+        "RuntimeService",
+        start_container,
+        runtime,
+        "StartContainer",
+        cri::StartContainerRequest,
+        cri::StartContainerResponse
+    );
+    unary!(
+        "RuntimeService",
+        list_containers,
+        status,
+        "ListContainers",
+        cri::ListContainersRequest,
+        cri::ListContainersResponse
+    );
+    unary!(
+        "RuntimeService",
+        status,
+        status,
+        "Status",
+        cri::StatusRequest,
+        cri::StatusResponse
+    );
+    unary!(
+        "RuntimeService",
+        container_status,
+        status,
+        "ContainerStatus",
+        cri::ContainerStatusRequest,
+        cri::ContainerStatusResponse
+    );
+}
+
+/// Client for the CRI `ImageService`.
+#[derive(Clone)]
+pub struct ImageClient {
+    inner: Grpc<Channel>,
+    version: ApiVersion,
+    connection: Connection,
+}
+
+impl ImageClient {
+    pub fn new(channel: Channel, version: ApiVersion, connection: Connection) -> Self {
+        ImageClient {
+            inner: Grpc::new(channel),
+            version,
+            connection,
+        }
+    }
+
+    unary!(
+        "ImageService",
+        image_status,
+        status,
+        "ImageStatus",
+        cri::ImageStatusRequest,
+        cri::ImageStatusResponse
+    );
+    unary!(
+        "ImageService",
+        pull_image,
+        image_pull,
+        "PullImage",
+        cri::PullImageRequest,
+        cri::PullImageResponse
+    );
+    unary!(
+        "ImageService",
+        image_fs_info,
+        status,
+        "ImageFsInfo",
+        cri::ImageFsInfoRequest,
+        cri::ImageFsInfoResponse
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{connection, FakeRuntime, Reply};
+    use crate::runtime::{is_retryable, Service};
+    use std::time::Duration;
+
+    /// A runtime which speaks `v1alpha2` and never answers `method`.
+    fn hanging(name: &str, method: &'static str) -> FakeRuntime {
+        let runtime = FakeRuntime::new(name);
+        runtime.listen(move |path| {
+            if path.ends_with(method) && path.starts_with("/runtime.v1alpha2.") {
+                Reply::Hang
+            } else {
+                Reply::Status(tonic::Code::Unimplemented)
+            }
+        });
+        runtime
+    }
+
+    #[tokio::test]
+    async fn calls_exceed_their_deadline() {
+        let runtime = hanging("deadline", "/Version");
+        let mut connection = connection(&runtime, Service::Runtime);
+        connection.timeouts.status = Duration::from_millis(50);
+        let mut client = connection.runtime_client().await.unwrap();
+
+        let started = std::time::Instant::now();
+        let error = client
+            .version(cri::VersionRequest::default())
+            .await
+            .unwrap_err();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(error.status().code(), tonic::Code::DeadlineExceeded);
+        assert!(is_retryable(&anyhow::Error::new(error)));
+        // The runtime is still reachable, so the channel is kept.
+        assert!(connection.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn uses_the_timeout_of_each_operation() {
+        let runtime = hanging("deadline-pull", "/PullImage");
+        let mut connection = connection(&runtime, Service::Image);
+        connection.timeouts.status = Duration::from_millis(10);
+        connection.timeouts.image_pull = Duration::from_secs(60);
+        let mut client = connection.image_client().await.unwrap();
+
+        // Pulls are allowed longer than the status deadline.
+        let pull = client.pull_image(cri::PullImageRequest::default());
+        assert!(tokio::time::timeout(Duration::from_millis(100), pull)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use k8s_cri::v1alpha2 as cri;
+use log::{debug, info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tonic::transport::Channel;
+
+use super::client::{ApiVersion, ImageClient, RuntimeClient};
+use crate::config::{CriEndpoint, Timeouts};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+const MAX_ATTEMPTS: u32 = 10;
+
+/// The CRI service a connection is used for, which determines how its API version is probed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Service {
+    Runtime,
+    Image,
+}
+
+/// A long-lived gRPC channel to a CRI endpoint, shared by every client.
+///
+/// The channel is dialed on first use, at which point the CRI API version is negotiated. When a
+/// call reports that the runtime is unavailable the channel is discarded, and the next caller
+/// redials it with exponential backoff.
+#[derive(Clone)]
+pub struct Connection {
+    endpoint: CriEndpoint,
+    service: Service,
+    pub(super) timeouts: Timeouts,
+    /// Only ever locked briefly and never across an `.await`, so discarding the channel cannot
+    /// be held up by a reconnect.
+    channel: Arc<std::sync::Mutex<Option<(Channel, ApiVersion)>>>,
+    /// Held for a single dial attempt, so that concurrent callers wait for one reconnect rather
+    /// than each dialing the runtime. It is released while backing off.
+    dialing: Arc<Mutex<()>>,
+    healthy: Arc<AtomicBool>,
+    pub(super) initial_backoff: Duration,
+    pub(super) max_attempts: u32,
+}
+
+impl Connection {
+    pub fn new(endpoint: CriEndpoint, service: Service, timeouts: Timeouts) -> Self {
+        Connection {
+            endpoint,
+            service,
+            timeouts,
+            channel: Arc::new(std::sync::Mutex::new(None)),
+            dialing: Arc::new(Mutex::new(())),
+            healthy: Arc::new(AtomicBool::new(false)),
+            initial_backoff: INITIAL_BACKOFF,
+            max_attempts: MAX_ATTEMPTS,
+        }
+    }
+
+    pub fn endpoint(&self) -> &CriEndpoint {
+        &self.endpoint
+    }
+
+    /// Whether the last dial succeeded and no call has since reported the runtime unavailable.
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::SeqCst)
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    pub async fn runtime_client(&self) -> anyhow::Result<RuntimeClient> {
+        let (channel, version) = self.channel().await?;
+        Ok(RuntimeClient::new(channel, version, self.clone()))
+    }
+
+    pub async fn image_client(&self) -> anyhow::Result<ImageClient> {
+        let (channel, version) = self.channel().await?;
+        Ok(ImageClient::new(channel, version, self.clone()))
+    }
+
+    async fn channel(&self) -> anyhow::Result<(Channel, ApiVersion)> {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            if let Some(channel) = self.cached() {
+                return Ok(channel);
+            }
+            let result = {
+                let _dialing = self.dialing.lock().await;
+                // Another caller may have reconnected while this one waited for the lock.
+                if let Some(channel) = self.cached() {
+                    return Ok(channel);
+                }
+                self.dial().await
+            };
+            match result {
+                Ok(channel) => return Ok(channel),
+                Err(e) if attempt < self.max_attempts => {
+                    warn!(
+                        "Error connecting to {} (attempt {}), retrying in {:?}: {:?}",
+                        &self.endpoint, attempt, backoff, &e
+                    );
+                    tokio::time::delay_for(backoff).await;
+                    backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+                    attempt += 1;
+                }
+                Err(e) => anyhow::bail!(e),
+            }
+        }
+    }
+
+    fn cached(&self) -> Option<(Channel, ApiVersion)> {
+        self.channel.lock().unwrap().clone()
+    }
+
+    async fn dial(&self) -> anyhow::Result<(Channel, ApiVersion)> {
+        let result = self.connect().await;
+        self.healthy.store(result.is_ok(), Ordering::SeqCst);
+        if let Ok(connected) = &result {
+            info!(
+                "Connected to {} using CRI {}.",
+                &self.endpoint,
+                connected.1.as_str()
+            );
+            *self.channel.lock().unwrap() = Some(connected.clone());
+        }
+        result
+    }
+
+    async fn connect(&self) -> anyhow::Result<(Channel, ApiVersion)> {
+        let channel = self.endpoint.connect().await?;
+        let version = self.negotiate(&channel).await?;
+        Ok((channel, version))
+    }
+
+    /// Probes the endpoint for `runtime.v1`, falling back to `runtime.v1alpha2` when the runtime
+    /// does not implement it.
+    async fn negotiate(&self, channel: &Channel) -> anyhow::Result<ApiVersion> {
+        let probe = match self.service {
+            Service::Runtime => RuntimeClient::new(channel.clone(), ApiVersion::V1, self.clone())
+                .version(cri::VersionRequest {
+                    version: ApiVersion::V1.as_str().to_string(),
+                })
+                .await
+                .map(|_| ()),
+            Service::Image => ImageClient::new(channel.clone(), ApiVersion::V1, self.clone())
+                .image_fs_info(cri::ImageFsInfoRequest {})
+                .await
+                .map(|_| ()),
+        };
+        match probe {
+            Ok(()) => Ok(ApiVersion::V1),
+            Err(e) if e.status().code() == tonic::Code::Unimplemented => {
+                debug!(
+                    "{} does not implement CRI v1: {}",
+                    &self.endpoint,
+                    e.status().message()
+                );
+                Ok(ApiVersion::V1alpha2)
+            }
+            Err(e) => anyhow::bail!(e),
+        }
+    }
+
+    /// Inspects a failed call, discarding the channel if the runtime could not be reached.
+    pub fn report(&self, status: &tonic::Status) {
+        if status.code() == tonic::Code::Unavailable {
+            debug!("Runtime {} unavailable, dropping channel.", &self.endpoint);
+            self.healthy.store(false, Ordering::SeqCst);
+            *self.channel.lock().unwrap() = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{connection, unimplemented, FakeRuntime, Reply};
+
+    #[tokio::test]
+    async fn reuses_the_channel() {
+        let runtime = FakeRuntime::new("reuse");
+        runtime.listen(unimplemented);
+        let connection = connection(&runtime, Service::Runtime);
+        let calls: Vec<_> = (0..5)
+            .map(|_| {
+                let connection = connection.clone();
+                tokio::spawn(async move { connection.channel().await })
+            })
+            .collect();
+        for call in calls {
+            call.await.unwrap().unwrap();
+        }
+        assert_eq!(runtime.accepted().await, 1);
+        assert!(connection.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn redials_after_the_runtime_is_unavailable() {
+        let runtime = FakeRuntime::new("redial");
+        runtime.listen(unimplemented);
+        let connection = connection(&runtime, Service::Runtime);
+        connection.channel().await.unwrap();
+
+        connection.report(&tonic::Status::not_found("sandbox"));
+        assert!(connection.is_healthy());
+        connection.channel().await.unwrap();
+        assert_eq!(runtime.accepted().await, 1);
+
+        connection.report(&tonic::Status::unavailable("restarting"));
+        assert!(!connection.is_healthy());
+        connection.channel().await.unwrap();
+        assert!(connection.is_healthy());
+        assert_eq!(runtime.accepted().await, 2);
+    }
+
+    #[tokio::test]
+    async fn backs_off_until_the_runtime_listens() {
+        let runtime = FakeRuntime::new("backoff");
+        let connection = connection(&runtime, Service::Runtime);
+        let dialing = tokio::spawn({
+            let connection = connection.clone();
+            async move { connection.channel().await }
+        });
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        assert!(!connection.is_healthy());
+        // The lock is not held while backing off, so reports are not held up by the reconnect.
+        connection.report(&tonic::Status::unavailable("restarting"));
+
+        runtime.listen(unimplemented);
+        dialing.await.unwrap().unwrap();
+        assert!(connection.is_healthy());
+        assert_eq!(runtime.accepted().await, 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let runtime = FakeRuntime::new("give-up");
+        let mut connection = connection(&runtime, Service::Runtime);
+        connection.max_attempts = 3;
+        let started = std::time::Instant::now();
+        assert!(connection.channel().await.is_err());
+        // Two backoffs of 10ms and 20ms.
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert!(!connection.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn negotiates_v1() {
+        let runtime = FakeRuntime::new("negotiate-v1");
+        runtime.listen(|path| match path {
+            "/runtime.v1.RuntimeService/Version" => Reply::message(&cri::VersionResponse {
+                runtime_api_version: "v1".to_string(),
+                ..Default::default()
+            }),
+            "/runtime.v1.ImageService/ImageFsInfo" => {
+                Reply::message(&cri::ImageFsInfoResponse::default())
+            }
+            _ => Reply::Status(tonic::Code::Unimplemented),
+        });
+        for service in [Service::Runtime, Service::Image].iter() {
+            let (_, version) = connection(&runtime, *service).channel().await.unwrap();
+            assert_eq!(version, ApiVersion::V1);
+        }
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_v1alpha2() {
+        let runtime = FakeRuntime::new("negotiate-v1alpha2");
+        runtime.listen(unimplemented);
+        for service in [Service::Runtime, Service::Image].iter() {
+            let (_, version) = connection(&runtime, *service).channel().await.unwrap();
+            assert_eq!(version, ApiVersion::V1alpha2);
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_to_negotiate_on_other_errors() {
+        let runtime = FakeRuntime::new("negotiate-denied");
+        runtime.listen(|_| Reply::Status(tonic::Code::PermissionDenied));
+        let mut connection = connection(&runtime, Service::Runtime);
+        connection.max_attempts = 1;
+        assert!(connection.channel().await.is_err());
+        assert!(!connection.is_healthy());
+    }
+}
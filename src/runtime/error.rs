@@ -0,0 +1,105 @@
+/// How a failed CRI call should be handled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// The runtime is unreachable, overloaded or too slow. The call may succeed if retried.
+    Retryable,
+    /// The sandbox, container or image does not exist.
+    NotFound,
+    /// The sandbox, container or image already exists.
+    AlreadyExists,
+    /// Retrying the call will not help.
+    Fatal,
+}
+
+/// A failed CRI call.
+#[derive(Debug)]
+pub struct CriError {
+    method: &'static str,
+    status: tonic::Status,
+}
+
+impl CriError {
+    pub fn new(method: &'static str, status: tonic::Status) -> Self {
+        CriError { method, status }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self.status.code() {
+            tonic::Code::Unavailable
+            | tonic::Code::DeadlineExceeded
+            | tonic::Code::ResourceExhausted
+            | tonic::Code::Aborted => ErrorKind::Retryable,
+            tonic::Code::NotFound => ErrorKind::NotFound,
+            tonic::Code::AlreadyExists => ErrorKind::AlreadyExists,
+            _ => ErrorKind::Fatal,
+        }
+    }
+
+    pub fn status(&self) -> &tonic::Status {
+        &self.status
+    }
+}
+
+impl std::fmt::Display for CriError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} failed with {:?}: {}",
+            self.method,
+            self.status.code(),
+            self.status.message()
+        )
+    }
+}
+
+impl std::error::Error for CriError {}
+
+/// Returns the kind of a CRI error wrapped in `error`, if it is one.
+pub fn error_kind(error: &anyhow::Error) -> Option<ErrorKind> {
+    error.downcast_ref::<CriError>().map(CriError::kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(code: tonic::Code) -> ErrorKind {
+        CriError::new("RunPodSandbox", tonic::Status::new(code, "")).kind()
+    }
+
+    #[test]
+    fn classifies_status_codes() {
+        for code in &[
+            tonic::Code::Unavailable,
+            tonic::Code::DeadlineExceeded,
+            tonic::Code::ResourceExhausted,
+            tonic::Code::Aborted,
+        ] {
+            assert_eq!(kind(*code), ErrorKind::Retryable, "{:?}", code);
+        }
+        assert_eq!(kind(tonic::Code::NotFound), ErrorKind::NotFound);
+        assert_eq!(kind(tonic::Code::AlreadyExists), ErrorKind::AlreadyExists);
+        for code in &[
+            tonic::Code::Unknown,
+            tonic::Code::InvalidArgument,
+            tonic::Code::PermissionDenied,
+            tonic::Code::FailedPrecondition,
+            tonic::Code::Unimplemented,
+            tonic::Code::Internal,
+        ] {
+            assert_eq!(kind(*code), ErrorKind::Fatal, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn finds_the_kind_of_wrapped_errors() {
+        let error = CriError::new("PullImage", tonic::Status::unavailable("restarting"));
+        assert_eq!(
+            error.to_string(),
+            "PullImage failed with Unavailable: restarting"
+        );
+        let error = anyhow::Error::new(error).context("pulling images");
+        assert_eq!(error_kind(&error), Some(ErrorKind::Retryable));
+        assert_eq!(error_kind(&anyhow::anyhow!("not a CRI error")), None);
+    }
+}
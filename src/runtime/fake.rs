@@ -0,0 +1,162 @@
+//! A fake CRI runtime for tests, serving canned gRPC replies on a Unix socket.
+
+use hyper::body::{Bytes, HttpBody};
+use hyper::{Body, HeaderMap, Request, Response};
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::net::UnixListener;
+
+use super::Connection;
+use crate::config::{CriEndpoint, Timeouts};
+
+/// How the fake runtime answers a call.
+pub enum Reply {
+    /// Succeeds with this encoded message.
+    Message(Vec<u8>),
+    /// Fails with this status code.
+    Status(tonic::Code),
+    /// Never answers.
+    Hang,
+}
+
+impl Reply {
+    pub fn message(message: &impl prost::Message) -> Self {
+        let mut buf = vec![];
+        message.encode(&mut buf).unwrap();
+        Reply::Message(buf)
+    }
+}
+
+type Handler = Arc<dyn Fn(&str) -> Reply + Send + Sync>;
+
+/// A socket unique to one test, removed when dropped.
+pub struct FakeRuntime {
+    path: PathBuf,
+    accepted: Arc<AtomicUsize>,
+}
+
+impl FakeRuntime {
+    /// Reserves a socket for the test without listening on it yet.
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("krustlet-cri-{}-{}.sock", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        FakeRuntime {
+            path,
+            accepted: Default::default(),
+        }
+    }
+
+    /// Starts answering calls, by their gRPC method path, with `handler`.
+    pub fn listen(&self, handler: impl Fn(&str) -> Reply + Send + Sync + 'static) {
+        let handler: Handler = Arc::new(handler);
+        let mut listener = UnixListener::bind(&self.path).unwrap();
+        let accepted = self.accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let handler = handler.clone();
+                let service = hyper::service::service_fn(move |request: Request<Body>| {
+                    respond(handler(request.uri().path()))
+                });
+                tokio::spawn(
+                    hyper::server::conn::Http::new()
+                        .http2_only(true)
+                        .serve_connection(stream, service),
+                );
+            }
+        });
+    }
+
+    pub fn endpoint(&self) -> CriEndpoint {
+        CriEndpoint::Unix(self.path.clone())
+    }
+
+    /// The number of connections accepted so far.
+    ///
+    /// Waits briefly first, so that connections the client has opened are counted.
+    pub async fn accepted(&self) -> usize {
+        tokio::time::delay_for(std::time::Duration::from_millis(20)).await;
+        self.accepted.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for FakeRuntime {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Answers every call with Unimplemented, so connections fall back to `v1alpha2`.
+pub fn unimplemented(_: &str) -> Reply {
+    Reply::Status(tonic::Code::Unimplemented)
+}
+
+/// A connection to `runtime` which backs off briefly between dial attempts.
+pub fn connection(runtime: &FakeRuntime, service: super::Service) -> Connection {
+    let timeout = std::time::Duration::from_secs(1);
+    let timeouts = Timeouts {
+        runtime: timeout,
+        image_pull: timeout,
+        status: timeout,
+    };
+    let mut connection = Connection::new(runtime.endpoint(), service, timeouts);
+    connection.initial_backoff = std::time::Duration::from_millis(10);
+    connection
+}
+
+async fn respond(reply: Reply) -> Result<Response<Unary>, Infallible> {
+    let response = Response::builder().header("content-type", "application/grpc");
+    let response = match reply {
+        Reply::Message(message) => {
+            // Length-prefixed, uncompressed gRPC message frame.
+            let mut frame = vec![0];
+            frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            frame.extend_from_slice(&message);
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", "0".parse().unwrap());
+            response.body(Unary {
+                frame: Some(frame.into()),
+                trailers: Some(trailers),
+            })
+        }
+        Reply::Hang => std::future::pending().await,
+        // A trailers-only response.
+        Reply::Status(code) => response
+            .header("grpc-status", (code as i32).to_string())
+            .body(Unary {
+                frame: None,
+                trailers: None,
+            }),
+    };
+    Ok(response.unwrap())
+}
+
+/// A response body of at most one message, followed by trailers.
+struct Unary {
+    frame: Option<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+impl HttpBody for Unary {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Infallible>>> {
+        Poll::Ready(self.get_mut().frame.take().map(Ok))
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Infallible>> {
+        Poll::Ready(Ok(self.get_mut().trailers.take()))
+    }
+}
@@ -0,0 +1,410 @@
+use k8s_cri::v1alpha2 as cri;
+use k8s_openapi::api::core::v1::{Node, NodeCondition};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use kube::api::{PatchParams, PatchStrategy};
+use log::{debug, error, info, warn};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use super::Connection;
+use crate::node::info::NodeInfo;
+
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// A CRI runtime condition as last observed by the watchdog.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    pub ready: bool,
+    pub reason: String,
+    pub message: String,
+}
+
+impl Condition {
+    fn unknown() -> Self {
+        Condition {
+            ready: false,
+            reason: "Unknown".to_string(),
+            message: "Runtime status has not been polled yet.".to_string(),
+        }
+    }
+
+    fn from_status(status: &cri::StatusResponse, condition_type: &str) -> Self {
+        let condition = status
+            .status
+            .as_ref()
+            .and_then(|status| {
+                status
+                    .conditions
+                    .iter()
+                    .find(|condition| condition.r#type == condition_type)
+            })
+            .cloned();
+        match condition {
+            Some(condition) => Condition {
+                ready: condition.status,
+                reason: condition.reason,
+                message: condition.message,
+            },
+            None => Condition {
+                ready: false,
+                reason: "ConditionMissing".to_string(),
+                message: format!("Runtime did not report {}.", condition_type),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Health {
+    runtime: Condition,
+    network: Condition,
+    ready_since: Time,
+    network_since: Time,
+}
+
+/// Polls the CRI `Status` RPC and mirrors `RuntimeReady` and `NetworkReady` onto the Node's
+/// `Ready` and `NetworkUnavailable` conditions, alongside the host's `nodeInfo`.
+///
+/// The watchdog is the only writer of the node's conditions. The Kubelet would otherwise reset
+/// them to `Ready` on every heartbeat, so its requests pass through [`crate::node::proxy`], which
+/// substitutes these conditions when the node is created and drops them from heartbeats.
+#[derive(Clone)]
+pub struct Watchdog {
+    health: Arc<RwLock<Health>>,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        let now = Time(chrono::Utc::now());
+        Watchdog {
+            health: Arc::new(RwLock::new(Health {
+                runtime: Condition::unknown(),
+                network: Condition::unknown(),
+                ready_since: now.clone(),
+                network_since: now,
+            })),
+        }
+    }
+}
+
+impl Watchdog {
+    pub async fn runtime_ready(&self) -> bool {
+        self.health.read().await.runtime.ready
+    }
+
+    pub async fn network_ready(&self) -> bool {
+        self.health.read().await.network.ready
+    }
+
+    /// The node conditions as of the last poll.
+    pub async fn conditions(&self) -> Vec<NodeCondition> {
+        self.health
+            .read()
+            .await
+            .conditions(Time(chrono::Utc::now()))
+    }
+
+    pub async fn run(self, connection: Connection, kubeconfig: kube::Config, node_name: String) {
+        let nodes: kube::Api<Node> = kube::Api::all(kube::Client::new(kubeconfig));
+        let node_info = NodeInfo::detect().await;
+        loop {
+            // A call has found the runtime unavailable since the last poll. Redialing backs off
+            // for a while, so publish that first rather than leaving the node ready meanwhile.
+            if !connection.is_healthy() && self.runtime_ready().await {
+                let lost = unreachable("Runtime connection was lost.".to_string());
+                self.publish(&nodes, &node_name, &node_info, lost).await;
+            }
+            let polled = poll(&connection).await;
+            self.publish(&nodes, &node_name, &node_info, polled).await;
+            tokio::time::delay_for(POLL_INTERVAL).await;
+        }
+    }
+
+    async fn publish(
+        &self,
+        nodes: &kube::Api<Node>,
+        node_name: &str,
+        node_info: &NodeInfo,
+        (runtime, network): (Condition, Condition),
+    ) {
+        let conditions = self.update(runtime, network).await;
+        if let Err(e) = patch_status(nodes, node_name, conditions, node_info).await {
+            error!("Error updating node status: {:?}", &e);
+        }
+    }
+
+    async fn update(&self, runtime: Condition, network: Condition) -> Vec<NodeCondition> {
+        let mut health = self.health.write().await;
+        let now = Time(chrono::Utc::now());
+        let was_ready = health.runtime.ready && health.network.ready;
+        let ready = runtime.ready && network.ready;
+        if ready != was_ready {
+            info!(
+                "Runtime ready changed to {}: {:?} {:?}",
+                ready, &runtime, &network
+            );
+            health.ready_since = now.clone();
+        }
+        if network.ready != health.network.ready {
+            health.network_since = now.clone();
+        }
+        health.runtime = runtime;
+        health.network = network;
+        health.conditions(now)
+    }
+}
+
+impl Health {
+    fn conditions(&self, now: Time) -> Vec<NodeCondition> {
+        let (ready, reason, message) = if !self.runtime.ready {
+            (
+                "False",
+                "KubeletNotReady",
+                format!(
+                    "container runtime not ready: RuntimeReady=false reason:{} message:{}",
+                    &self.runtime.reason, &self.runtime.message
+                ),
+            )
+        } else if !self.network.ready {
+            (
+                "False",
+                "KubeletNotReady",
+                format!(
+                    "container runtime network not ready: NetworkReady=false reason:{} message:{}",
+                    &self.network.reason, &self.network.message
+                ),
+            )
+        } else {
+            (
+                "True",
+                "KubeletReady",
+                "kubelet is posting ready status".to_string(),
+            )
+        };
+        let network_unavailable = if self.network.ready { "False" } else { "True" };
+        vec![
+            NodeCondition {
+                type_: "Ready".to_string(),
+                status: ready.to_string(),
+                reason: Some(reason.to_string()),
+                message: Some(message),
+                last_heartbeat_time: Some(now.clone()),
+                last_transition_time: Some(self.ready_since.clone()),
+            },
+            NodeCondition {
+                type_: "NetworkUnavailable".to_string(),
+                status: network_unavailable.to_string(),
+                reason: Some(self.network.reason.clone()),
+                message: Some(self.network.message.clone()),
+                last_heartbeat_time: Some(now),
+                last_transition_time: Some(self.network_since.clone()),
+            },
+        ]
+    }
+}
+
+/// Returns the `RuntimeReady` and `NetworkReady` conditions reported by the runtime.
+async fn poll(connection: &Connection) -> (Condition, Condition) {
+    let mut client = match connection.runtime_client().await {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Error creating client: {:?}", &e);
+            return unreachable(e.to_string());
+        }
+    };
+    let request = tonic::Request::new(cri::StatusRequest { verbose: false });
+    debug!("Sending request: {:?}", &request);
+    match client.status(request).await {
+        Ok(response) => (
+            Condition::from_status(&response, "RuntimeReady"),
+            Condition::from_status(&response, "NetworkReady"),
+        ),
+        Err(e) => unreachable(e.status().message().to_string()),
+    }
+}
+
+fn unreachable(message: String) -> (Condition, Condition) {
+    let condition = Condition {
+        ready: false,
+        reason: "ContainerRuntimeUnreachable".to_string(),
+        message,
+    };
+    (condition.clone(), condition)
+}
+
+async fn patch_status(
+    nodes: &kube::Api<Node>,
+    node_name: &str,
+    conditions: Vec<NodeCondition>,
+    node_info: &NodeInfo,
+) -> anyhow::Result<()> {
+    let patch = serde_json::json!({
+        "status": {
+            "conditions": conditions,
+            "nodeInfo": node_info.to_json(),
+        }
+    });
+    // Conditions are merged by type, so a strategic merge leaves any conditions set by other
+    // controllers in place.
+    let params = PatchParams {
+        patch_strategy: PatchStrategy::Strategic,
+        ..Default::default()
+    };
+    nodes
+        .patch_status(node_name, &params, serde_json::to_vec(&patch)?)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ready() -> Condition {
+        Condition {
+            ready: true,
+            reason: String::new(),
+            message: String::new(),
+        }
+    }
+
+    fn not_ready(reason: &str) -> Condition {
+        Condition {
+            ready: false,
+            reason: reason.to_string(),
+            message: "not ready".to_string(),
+        }
+    }
+
+    fn health(runtime: Condition, network: Condition) -> Health {
+        let since = Time(chrono::Utc::now());
+        Health {
+            runtime,
+            network,
+            ready_since: since.clone(),
+            network_since: since,
+        }
+    }
+
+    fn summary(conditions: &[NodeCondition]) -> Vec<(&str, &str, &str)> {
+        conditions
+            .iter()
+            .map(|condition| {
+                (
+                    condition.type_.as_str(),
+                    condition.status.as_str(),
+                    condition.reason.as_deref().unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_conditions_from_the_status() {
+        let status = cri::StatusResponse {
+            status: Some(cri::RuntimeStatus {
+                conditions: vec![
+                    cri::RuntimeCondition {
+                        r#type: "RuntimeReady".to_string(),
+                        status: true,
+                        ..Default::default()
+                    },
+                    cri::RuntimeCondition {
+                        r#type: "NetworkReady".to_string(),
+                        status: false,
+                        reason: "NetworkPluginNotReady".to_string(),
+                        message: "cni config uninitialized".to_string(),
+                    },
+                ],
+            }),
+            ..Default::default()
+        };
+        assert_eq!(Condition::from_status(&status, "RuntimeReady"), ready());
+        assert_eq!(
+            Condition::from_status(&status, "NetworkReady"),
+            Condition {
+                ready: false,
+                reason: "NetworkPluginNotReady".to_string(),
+                message: "cni config uninitialized".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn missing_conditions_are_not_ready() {
+        let status = cri::StatusResponse::default();
+        let condition = Condition::from_status(&status, "RuntimeReady");
+        assert!(!condition.ready);
+        assert_eq!(condition.reason, "ConditionMissing");
+    }
+
+    #[test]
+    fn reports_ready() {
+        let conditions = health(ready(), ready()).conditions(Time(chrono::Utc::now()));
+        assert_eq!(
+            summary(&conditions),
+            vec![
+                ("Ready", "True", "KubeletReady"),
+                ("NetworkUnavailable", "False", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_runtime_before_the_network() {
+        let conditions =
+            health(not_ready("Down"), not_ready("NoCNI")).conditions(Time(chrono::Utc::now()));
+        assert_eq!(
+            summary(&conditions),
+            vec![
+                ("Ready", "False", "KubeletNotReady"),
+                ("NetworkUnavailable", "True", "NoCNI"),
+            ]
+        );
+        assert!(conditions[0]
+            .message
+            .as_ref()
+            .unwrap()
+            .starts_with("container runtime not ready: RuntimeReady=false reason:Down"));
+    }
+
+    #[test]
+    fn reports_the_network() {
+        let conditions = health(ready(), not_ready("NoCNI")).conditions(Time(chrono::Utc::now()));
+        assert_eq!(
+            summary(&conditions),
+            vec![
+                ("Ready", "False", "KubeletNotReady"),
+                ("NetworkUnavailable", "True", "NoCNI"),
+            ]
+        );
+        assert!(conditions[0]
+            .message
+            .as_ref()
+            .unwrap()
+            .starts_with("container runtime network not ready"));
+    }
+
+    #[tokio::test]
+    async fn tracks_transition_times() {
+        let watchdog = Watchdog::default();
+        let initial = watchdog.conditions().await;
+
+        tokio::time::delay_for(std::time::Duration::from_millis(10)).await;
+        let conditions = watchdog.update(ready(), not_ready("NoCNI")).await;
+        // Still not ready, but the network reason changed without a transition.
+        assert_eq!(
+            conditions[0].last_transition_time,
+            initial[0].last_transition_time
+        );
+        assert_eq!(
+            conditions[1].last_transition_time,
+            initial[1].last_transition_time
+        );
+
+        let conditions = watchdog.update(ready(), ready()).await;
+        assert!(conditions[0].last_transition_time > initial[0].last_transition_time);
+        assert!(conditions[1].last_transition_time > initial[1].last_transition_time);
+        assert!(watchdog.runtime_ready().await && watchdog.network_ready().await);
+    }
+}
@@ -0,0 +1,16 @@
+mod client;
+mod connection;
+mod error;
+#[cfg(test)]
+mod fake;
+mod health;
+
+pub use client::{ImageClient, RuntimeClient};
+pub use connection::{Connection, Service};
+pub use error::{error_kind, CriError, ErrorKind};
+pub use health::Watchdog;
+
+/// Whether an error is a CRI call which may succeed if retried.
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    error_kind(error) == Some(ErrorKind::Retryable)
+}
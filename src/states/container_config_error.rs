@@ -0,0 +1,38 @@
+use log::info;
+
+use super::{make_pod_status, starting::Starting, PodState, RETRY_DELAY};
+use kubelet::state::prelude::*;
+
+/// A container's configuration could not be resolved, such as a missing ConfigMap key.
+#[derive(Default, Debug)]
+pub struct CreateContainerConfigError {
+    pub message: String,
+}
+
+#[async_trait::async_trait]
+impl State<PodState> for CreateContainerConfigError {
+    async fn next(
+        self: Box<Self>,
+        _pod_state: &mut PodState,
+        pod: &Pod,
+    ) -> anyhow::Result<Transition<PodState>> {
+        // The missing object may be created at any time, so keep retrying.
+        tokio::time::delay_for(RETRY_DELAY).await;
+        info!("Retrying container configuration of pod {}.", pod.name());
+        Ok(Transition::next(self, Starting))
+    }
+
+    async fn json_status(
+        &self,
+        pod_state: &mut PodState,
+        _pod: &Pod,
+    ) -> anyhow::Result<serde_json::Value> {
+        make_pod_status(
+            pod_state,
+            Phase::Pending,
+            &format!("CreateContainerConfigError: {}", &self.message),
+        )
+    }
+}
+
+impl TransitionTo<Starting> for CreateContainerConfigError {}
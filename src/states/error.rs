@@ -1,6 +1,6 @@
 use kubelet::state::prelude::*;
 
-use super::PodState;
+use super::{make_pod_status, PodState};
 
 #[derive(Default, Debug)]
 /// The Pod failed to run.
@@ -15,14 +15,44 @@ impl State<PodState> for Error {
         _pod_state: &mut PodState,
         _pod: &Pod,
     ) -> anyhow::Result<Transition<PodState>> {
-        unimplemented!()
+        Ok(Transition::Complete(Err(anyhow::anyhow!(self.message))))
     }
 
     async fn json_status(
         &self,
+        pod_state: &mut PodState,
+        _pod: &Pod,
+    ) -> anyhow::Result<serde_json::Value> {
+        make_pod_status(pod_state, Phase::Failed, &self.message)
+    }
+}
+
+#[derive(Default, Debug)]
+/// The Pod was rejected at admission and will not be run on this node.
+pub struct Rejected {
+    /// Cause of the rejection, matching the reasons reported by upstream admission.
+    pub reason: String,
+    pub message: String,
+}
+
+#[async_trait::async_trait]
+impl State<PodState> for Rejected {
+    async fn next(
+        self: Box<Self>,
         _pod_state: &mut PodState,
         _pod: &Pod,
+    ) -> anyhow::Result<Transition<PodState>> {
+        // Completing with an error would replace the reported reason with the error itself.
+        Ok(Transition::Complete(Ok(())))
+    }
+
+    async fn json_status(
+        &self,
+        pod_state: &mut PodState,
+        _pod: &Pod,
     ) -> anyhow::Result<serde_json::Value> {
-        make_status(Phase::Pending, &self.message)
+        let mut status = make_pod_status(pod_state, Phase::Failed, &self.reason)?;
+        status["status"]["message"] = serde_json::json!(&self.message);
+        Ok(status)
     }
 }
@@ -1,33 +1,17 @@
 use async_trait::async_trait;
 use k8s_cri::v1alpha2 as cri;
-use log::{debug, error, info};
-use std::convert::TryFrom;
-use tokio::net::UnixStream;
-use tonic::transport::{Channel, Endpoint, Uri};
-use tower::service_fn;
+use log::{debug, error, info, warn};
 
-use super::{error::Error, starting::Starting, PodState};
+use super::{error::Error, make_pod_status, starting::Starting, PodState, RETRY_DELAY};
+use crate::pod::namespaces;
+use crate::runtime::{is_retryable, ImageClient};
 use kubelet::state::prelude::*;
 
 /// Kubelet is pulling container images.
 #[derive(Default, Debug)]
 pub struct ImagePull;
 
-async fn make_image_client(
-    path: &'static str,
-) -> anyhow::Result<cri::image_service_client::ImageServiceClient<Channel>> {
-    let channel = Endpoint::try_from("lttp://[::]:50051")?
-        .connect_with_connector(service_fn(move |_: Uri| UnixStream::connect(path)))
-        .await?;
-
-    let client = cri::image_service_client::ImageServiceClient::new(channel);
-    Ok(client)
-}
-
-async fn image_present(
-    image_client: &mut cri::image_service_client::ImageServiceClient<Channel>,
-    image: &str,
-) -> anyhow::Result<bool> {
+async fn image_present(image_client: &mut ImageClient, image: &str) -> anyhow::Result<bool> {
     let request = tonic::Request::new(cri::ImageStatusRequest {
         image: Some(cri::ImageSpec {
             image: image.to_string(),
@@ -35,18 +19,12 @@ async fn image_present(
         verbose: false,
     });
     debug!("Sending request: {:?}", &request);
-    let response = match image_client.image_status(request).await {
-        Ok(response) => response.into_inner(),
-        Err(e) => {
-            error!("Error making request: {:?}", &e);
-            anyhow::bail!(e);
-        }
-    };
+    let response = image_client.image_status(request).await?;
     Ok(response.image.is_some())
 }
 
 async fn pull_image(
-    image_client: &mut cri::image_service_client::ImageServiceClient<Channel>,
+    image_client: &mut ImageClient,
     image: &str,
     sandbox_config: &cri::PodSandboxConfig,
 ) -> anyhow::Result<()> {
@@ -60,17 +38,39 @@ async fn pull_image(
         sandbox_config: Some(sandbox_config.clone()),
     });
     debug!("Sending request: {:?}", &request);
-    let response = match image_client.pull_image(request).await {
-        Ok(response) => response.into_inner(),
-        Err(e) => {
-            error!("Error making request: {:?}", &e);
-            anyhow::bail!(e);
-        }
-    };
+    let response = image_client.pull_image(request).await?;
     info!("Pulled image: {:?}", response);
     Ok(())
 }
 
+/// Pulls each container image according to its pull policy.
+async fn pull_images(
+    pod_state: &PodState,
+    pod: &Pod,
+    image_client: &mut ImageClient,
+) -> anyhow::Result<()> {
+    for container in pod.containers() {
+        let image: String = container.image()?.unwrap().into();
+        let pull_policy = container.effective_pull_policy()?;
+        info!("Image pull policy: {:?}", pull_policy);
+        match pull_policy {
+            kubelet::container::PullPolicy::Always => {
+                pull_image(image_client, &image, &pod_state.sandbox_config).await?
+            }
+            kubelet::container::PullPolicy::IfNotPresent => {
+                if !image_present(image_client, &image).await? {
+                    info!("Image not present.");
+                    pull_image(image_client, &image, &pod_state.sandbox_config).await?
+                } else {
+                    info!("Image present.");
+                }
+            }
+            kubelet::container::PullPolicy::Never => (),
+        }
+    }
+    Ok(())
+}
+
 #[async_trait]
 impl State<PodState> for ImagePull {
     async fn next(
@@ -78,7 +78,21 @@ impl State<PodState> for ImagePull {
         pod_state: &mut PodState,
         pod: &Pod,
     ) -> anyhow::Result<Transition<PodState>> {
-        let mut image_client = match make_image_client(pod_state.shared.socket_address).await {
+        // Pods sharing the host network namespace do not depend on the runtime network.
+        let host_network = namespaces::host_network(pod.as_kube_pod());
+        let health = &pod_state.shared.health;
+        if !health.runtime_ready().await || !(host_network || health.network_ready().await) {
+            info!(
+                "Waiting for runtime network before starting pod {}.",
+                pod.name()
+            );
+            // Re-enter this state rather than looping here, so each wait is bounded and the
+            // pod's status is refreshed between checks.
+            tokio::time::delay_for(RETRY_DELAY).await;
+            return Ok(Transition::next(self, ImagePull));
+        }
+
+        let mut image_client = match pod_state.shared.image.image_client().await {
             Ok(client) => client,
             Err(e) => {
                 let message = format!("Error creating image client: {:?}", &e);
@@ -87,36 +101,33 @@ impl State<PodState> for ImagePull {
             }
         };
 
-        for container in pod.containers() {
-            let image: String = container.image()?.unwrap().into();
-            let pull_policy = container.effective_pull_policy()?;
-            info!("Image pull policy: {:?}", pull_policy);
-            match pull_policy {
-                kubelet::container::PullPolicy::Always => {
-                    pull_image(&mut image_client, &image, &pod_state.sandbox_config).await?
-                }
-                kubelet::container::PullPolicy::IfNotPresent => {
-                    if !image_present(&mut image_client, &image).await? {
-                        info!("Image not present.");
-                        pull_image(&mut image_client, &image, &pod_state.sandbox_config).await?
-                    } else {
-                        info!("Image present.");
-                    }
-                }
-                kubelet::container::PullPolicy::Never => (),
+        if let Err(e) = pull_images(pod_state, pod, &mut image_client).await {
+            // The runtime went away mid-pull, most likely because it is restarting, or the pull
+            // ran out of time. Either may succeed if retried; a lost channel is redialed.
+            if is_retryable(&e) {
+                warn!(
+                    "Error pulling images for pod {}, retrying: {:?}",
+                    pod.name(),
+                    &e
+                );
+                tokio::time::delay_for(RETRY_DELAY).await;
+                return Ok(Transition::next(self, ImagePull));
             }
+            return Err(e);
         }
+
         Ok(Transition::next(self, Starting))
     }
 
     async fn json_status(
         &self,
-        _pod_state: &mut PodState,
+        pod_state: &mut PodState,
         _pod: &Pod,
     ) -> anyhow::Result<serde_json::Value> {
-        make_status(Phase::Pending, "ImagePull")
+        make_pod_status(pod_state, Phase::Pending, "ImagePull")
     }
 }
 
 impl TransitionTo<Error> for ImagePull {}
+impl TransitionTo<ImagePull> for ImagePull {}
 impl TransitionTo<Starting> for ImagePull {}
@@ -1,11 +1,9 @@
 use async_trait::async_trait;
 use k8s_cri::v1alpha2 as cri;
 use log::{debug, error, info};
-use std::convert::TryFrom;
-use tokio::net::UnixStream;
-use tonic::transport::{Channel, Endpoint, Uri};
-use tower::service_fn;
+use std::sync::Arc;
 
+mod container_config_error;
 mod error;
 mod image_pull;
 mod registered;
@@ -16,26 +14,43 @@ mod terminated;
 pub(crate) use registered::Registered;
 pub(crate) use terminated::Terminated;
 
+use crate::config::Config;
+use crate::node::capacity::Resources;
+use crate::pod::ports::HostPorts;
+use crate::pod::qos::QosClass;
+use crate::pod::runtime_class::RuntimeClassConfig;
 use crate::provider::{ContainerMap, PodMap};
+use crate::runtime::{Connection, RuntimeClient, Watchdog};
+use kubelet::state::prelude::{make_status, Phase};
+
+/// Delay before a state retries after the runtime became unavailable or a CRI call exceeded its
+/// deadline.
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
 
 #[derive(Clone)]
 pub struct SharedPodState {
     pub pods: PodMap,
     pub containers: ContainerMap,
-    pub socket_address: &'static str,
+    pub config: Arc<Config>,
+    pub runtime: Connection,
+    pub image: Connection,
+    pub health: Watchdog,
     pub kubeconfig: kube::Config,
+    pub node_name: String,
+    /// Address of this node, reported as each pod's `status.hostIP`.
+    pub host_ip: String,
+    /// Directory holding per-pod files, such as managed hosts files.
+    pub pods_dir: std::path::PathBuf,
+    /// Directory `localhost/` seccomp profiles are resolved against.
+    pub seccomp_root: std::path::PathBuf,
+    /// Node allocatable as last reported, in thousandths of each resource's unit.
+    pub allocatable: Arc<tokio::sync::RwLock<Resources>>,
+    pub host_ports: HostPorts,
 }
 
 impl SharedPodState {
-    pub async fn client(
-        &self,
-    ) -> anyhow::Result<cri::runtime_service_client::RuntimeServiceClient<Channel>> {
-        let path = self.socket_address;
-        let channel = Endpoint::try_from("lttp://[::]:50051")?
-            .connect_with_connector(service_fn(move |_: Uri| UnixStream::connect(path)))
-            .await?;
-        let client = cri::runtime_service_client::RuntimeServiceClient::new(channel);
-        Ok(client)
+    pub async fn client(&self) -> anyhow::Result<RuntimeClient> {
+        self.runtime.runtime_client().await
     }
 
     pub async fn refresh_containers(&self) -> anyhow::Result<()> {
@@ -49,13 +64,7 @@ impl SharedPodState {
                 anyhow::bail!(e);
             }
         };
-        let response = match client.list_containers(request).await {
-            Ok(response) => response.into_inner(),
-            Err(e) => {
-                error!("Error making request: {:?}", &e);
-                anyhow::bail!(e);
-            }
-        };
+        let response = client.list_containers(request).await?;
 
         debug!("{:?}", &response);
         info!("Found {} containerss.", response.containers.len());
@@ -81,13 +90,7 @@ impl SharedPodState {
                 anyhow::bail!(e);
             }
         };
-        let response = match client.list_pod_sandbox(request).await {
-            Ok(response) => response.into_inner(),
-            Err(e) => {
-                error!("Error making request: {:?}", &e);
-                anyhow::bail!(e);
-            }
-        };
+        let response = client.list_pod_sandbox(request).await?;
 
         debug!("{:?}", &response);
         info!("Found {} pods.", response.items.len());
@@ -96,39 +99,131 @@ impl SharedPodState {
         *pods = std::collections::HashMap::new();
         for pod in response.items {
             if let Some(meta) = pod.metadata.clone() {
-                pods.insert((meta.namespace, meta.name), pod);
+                // Keep the latest sandbox if a pod has been restarted.
+                match pods.get(&meta.uid) {
+                    Some(existing) if existing.created_at > pod.created_at => (),
+                    _ => {
+                        pods.insert(meta.uid, pod);
+                    }
+                }
             }
         }
         Ok(())
     }
+
+    /// Returns the most recently created sandbox for a pod name, which belongs to its newest
+    /// generation.
+    pub async fn newest_pod(&self, namespace: &str, name: &str) -> Option<cri::PodSandbox> {
+        self.pods
+            .read()
+            .await
+            .values()
+            .filter(|pod| match &pod.metadata {
+                Some(meta) => meta.namespace == namespace && meta.name == name,
+                None => false,
+            })
+            .max_by_key(|pod| pod.created_at)
+            .cloned()
+    }
 }
 
 pub struct PodState {
     pub shared: SharedPodState,
     pub sandbox_config: cri::PodSandboxConfig,
+    pub runtime_class: RuntimeClassConfig,
+    pub qos_class: QosClass,
+    /// ID of the running sandbox, once it has been started.
+    pub sandbox_id: Option<String>,
+    /// Sandbox IPs as last reported by the runtime, primary first.
+    pub pod_ips: Vec<String>,
+    /// Why the pod cannot run, if its sandbox configuration could not be built. The pod is
+    /// failed by [`Registered`] rather than by `initialize_pod_state`, so the reason is reported
+    /// in its status.
+    pub rejected: Option<String>,
+}
+
+/// Builds a pod status patch which also reports the pod's QoS class.
+fn make_pod_status(
+    pod_state: &PodState,
+    phase: Phase,
+    reason: &str,
+) -> anyhow::Result<serde_json::Value> {
+    let mut status = make_status(phase, reason)?;
+    status["status"]["qosClass"] = serde_json::json!(pod_state.qos_class.as_str());
+    status["status"]["hostIP"] = serde_json::json!(&pod_state.shared.host_ip);
+    if let Some(pod_ip) = pod_state.pod_ips.first() {
+        let pod_ips: Vec<_> = pod_state
+            .pod_ips
+            .iter()
+            .map(|ip| serde_json::json!({ "ip": ip }))
+            .collect();
+        status["status"]["podIP"] = serde_json::json!(pod_ip);
+        status["status"]["podIPs"] = serde_json::json!(pod_ips);
+    }
+    Ok(status)
 }
 
 impl PodState {
     pub fn pod_name(&self) -> String {
         self.sandbox_config.metadata.as_ref().unwrap().name.clone()
     }
-    pub fn pod_namespace(&self) -> String {
+    pub fn pod_uid(&self) -> String {
+        self.sandbox_config.metadata.as_ref().unwrap().uid.clone()
+    }
+
+    fn host_network(&self) -> bool {
         self.sandbox_config
-            .metadata
+            .linux
             .as_ref()
-            .unwrap()
-            .namespace
-            .clone()
+            .and_then(|linux| linux.security_context.as_ref())
+            .and_then(|security_context| security_context.namespace_options.as_ref())
+            .map(|options| options.network == cri::NamespaceMode::Node as i32)
+            .unwrap_or(false)
+    }
+
+    /// Reads the sandbox IPs from the runtime, returning whether they changed. Pods using the
+    /// host network have the node's IP.
+    pub async fn refresh_ips(&mut self, client: &mut RuntimeClient) -> anyhow::Result<bool> {
+        let pod_sandbox_id = match &self.sandbox_id {
+            Some(id) => id.clone(),
+            None => return Ok(false),
+        };
+        let pod_ips = if self.host_network() {
+            vec![self.shared.host_ip.clone()]
+        } else {
+            let request = tonic::Request::new(cri::PodSandboxStatusRequest {
+                pod_sandbox_id,
+                verbose: false,
+            });
+            debug!("Sending request: {:?}", &request);
+            let response = client.pod_sandbox_status(request).await?;
+            match response.status.and_then(|status| status.network) {
+                Some(network) => std::iter::once(network.ip)
+                    .chain(network.additional_ips.into_iter().map(|ip| ip.ip))
+                    .filter(|ip| !ip.is_empty())
+                    .collect(),
+                None => vec![],
+            }
+        };
+        if pod_ips == self.pod_ips {
+            return Ok(false);
+        }
+        info!("Pod {} has IPs {:?}.", self.pod_name(), &pod_ips);
+        self.pod_ips = pod_ips;
+        Ok(true)
     }
 }
 
 #[async_trait]
 impl kubelet::state::AsyncDrop for PodState {
     async fn async_drop(self) {
-        self.shared
-            .pods
-            .write()
-            .await
-            .remove(&(self.pod_namespace(), self.pod_name()));
+        let uid = self.pod_uid();
+        self.shared.host_ports.release(&uid).await;
+        if let Err(e) = tokio::fs::remove_dir_all(self.shared.pods_dir.join(&uid)).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                error!("Error removing directory of pod {}: {:?}", &uid, &e);
+            }
+        }
+        self.shared.pods.write().await.remove(&uid);
     }
 }
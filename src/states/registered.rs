@@ -1,19 +1,28 @@
 use async_trait::async_trait;
-use log::info;
+use log::{error, info, warn};
 
+use super::error::{Error, Rejected};
 use super::image_pull::ImagePull;
-use super::PodState;
+use super::{make_pod_status, PodState, RETRY_DELAY};
+use crate::pod::runtime_class::{self, Unusable};
+use crate::pod::security_context;
 use kubelet::state::prelude::*;
 
+/// Upper bound on the delay between attempts to resolve a RuntimeClass.
+const MAX_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(80);
+
 /// The Kubelet is aware of the Pod.
 #[derive(Default, Debug)]
-pub struct Registered;
+pub struct Registered {
+    /// Failed attempts to resolve the pod's RuntimeClass so far.
+    retries: u32,
+}
 
 #[async_trait]
 impl State<PodState> for Registered {
     async fn next(
         self: Box<Self>,
-        _pod_state: &mut PodState,
+        pod_state: &mut PodState,
         pod: &Pod,
     ) -> anyhow::Result<Transition<PodState>> {
         info!(
@@ -21,16 +30,107 @@ impl State<PodState> for Registered {
             pod.namespace(),
             pod.name()
         );
+        if let Some(message) = pod_state.rejected.take() {
+            error!("Rejecting pod {}: {}", pod.name(), &message);
+            return Ok(Transition::next(self, Error { message }));
+        }
+
+        let client = kube::Client::new(pod_state.shared.kubeconfig.clone());
+        pod_state.runtime_class =
+            match runtime_class::resolve(client, &pod_state.shared.node_name, pod).await {
+                Ok(runtime_class) => runtime_class,
+                Err(e) if e.downcast_ref::<Unusable>().is_some() => {
+                    let message = format!("Error resolving RuntimeClass: {}", &e);
+                    error!("{}", message);
+                    return Ok(Transition::next(self, Error { message }));
+                }
+                Err(e) => {
+                    let delay =
+                        std::cmp::min(RETRY_DELAY * 2u32.pow(self.retries.min(4)), MAX_RETRY_DELAY);
+                    warn!(
+                        "Error resolving RuntimeClass for pod {}, retrying in {:?}: {:?}",
+                        pod.name(),
+                        delay,
+                        &e
+                    );
+                    tokio::time::delay_for(delay).await;
+                    let retries = self.retries + 1;
+                    return Ok(Transition::next(self, Registered { retries }));
+                }
+            };
+
+        if let Err(e) = security_context::check_sysctls(
+            pod.as_kube_pod(),
+            &pod_state.shared.config.allowed_unsafe_sysctls,
+        ) {
+            let message = format!("Pod rejected: {}", &e);
+            error!("{}", message);
+            return Ok(Transition::next(
+                self,
+                Rejected {
+                    reason: "SysctlForbidden".to_string(),
+                    message,
+                },
+            ));
+        }
+
+        if let Err(e) = security_context::check_seccomp_profiles(
+            pod.as_kube_pod(),
+            &pod_state.shared.seccomp_root,
+        )
+        .await
+        {
+            let message = format!("Pod rejected: {}", &e);
+            error!("{}", message);
+            return Ok(Transition::next(
+                self,
+                Rejected {
+                    reason: "SeccompProfile".to_string(),
+                    message,
+                },
+            ));
+        }
+        if let Err(e) = security_context::check_apparmor_profiles(pod.as_kube_pod()).await {
+            let message = format!("Pod rejected: {}", &e);
+            error!("{}", message);
+            return Ok(Transition::next(
+                self,
+                Rejected {
+                    reason: "AppArmor".to_string(),
+                    message,
+                },
+            ));
+        }
+
+        if let Err(e) = pod_state
+            .shared
+            .host_ports
+            .claim(pod_state.pod_uid(), &pod_state.sandbox_config.port_mappings)
+            .await
+        {
+            let message = format!("Pod rejected: {}", &e);
+            error!("{}", message);
+            return Ok(Transition::next(
+                self,
+                Rejected {
+                    reason: "PodFitsHostPorts".to_string(),
+                    message,
+                },
+            ));
+        }
         Ok(Transition::next(self, ImagePull))
     }
 
     async fn json_status(
         &self,
-        _pod_state: &mut PodState,
+        pod_state: &mut PodState,
         _pod: &Pod,
     ) -> anyhow::Result<serde_json::Value> {
-        make_status(Phase::Pending, "Registered")
+        make_pod_status(pod_state, Phase::Pending, "Registered")
     }
 }
 
+impl TransitionTo<Error> for Registered {}
 impl TransitionTo<ImagePull> for Registered {}
+impl TransitionTo<Registered> for Registered {}
+impl TransitionTo<Rejected> for Registered {}
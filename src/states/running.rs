@@ -1,6 +1,11 @@
-use super::PodState;
+use log::{error, warn};
+
+use super::{make_pod_status, PodState};
 use kubelet::state::prelude::*;
 
+/// How often the sandbox IPs are checked for changes.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
 /// The Kubelet is running the Pod.
 #[derive(Default, Debug)]
 pub struct Running;
@@ -9,20 +14,35 @@ pub struct Running;
 impl State<PodState> for Running {
     async fn next(
         self: Box<Self>,
-        _pod_state: &mut PodState,
-        _pod: &Pod,
+        pod_state: &mut PodState,
+        pod: &Pod,
     ) -> anyhow::Result<Transition<PodState>> {
         // TODO: Check for container exits.
         loop {
-            tokio::time::delay_for(std::time::Duration::from_secs(10)).await;
+            tokio::time::delay_for(POLL_INTERVAL).await;
+            let mut client = match pod_state.shared.client().await {
+                Ok(client) => client,
+                Err(e) => {
+                    error!("Error creating client: {:?}", &e);
+                    continue;
+                }
+            };
+            match pod_state.refresh_ips(&mut client).await {
+                // Re-entering the state publishes the new IPs in the pod status.
+                Ok(true) => return Ok(Transition::next(self, Running)),
+                Ok(false) => (),
+                Err(e) => warn!("Error refreshing IPs of pod {}: {:?}", pod.name(), &e),
+            }
         }
     }
 
     async fn json_status(
         &self,
-        _pod_state: &mut PodState,
+        pod_state: &mut PodState,
         _pod: &Pod,
     ) -> anyhow::Result<serde_json::Value> {
-        make_status(Phase::Running, "Running")
+        make_pod_status(pod_state, Phase::Running, "Running")
     }
 }
+
+impl TransitionTo<Running> for Running {}
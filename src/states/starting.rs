@@ -1,15 +1,285 @@
 use async_trait::async_trait;
 use k8s_cri::v1alpha2 as cri;
+use k8s_openapi::api::core::v1::Container;
 use log::{debug, error, info, warn};
+use std::collections::HashMap;
 
+use super::container_config_error::CreateContainerConfigError;
 use super::terminated::stop_and_delete_pod_sandbox;
-use super::{running::Running, PodState};
+use super::{make_pod_status, running::Running, PodState, RETRY_DELAY};
+use crate::node::capacity as node_capacity;
+use crate::pod::env::{self, ContainerConfigError};
+use crate::pod::security_context::{self, ImageUser};
+use crate::pod::{downward, expansion, hosts, labels, resources};
+use crate::runtime::{is_retryable, ErrorKind, ImageClient};
 use kubelet::state::prelude::*;
 
 /// The Kubelet is starting the Pod.
 #[derive(Default, Debug)]
 pub struct Starting;
 
+/// Returns the user an image runs as by default.
+async fn image_user(image_client: &mut ImageClient, image: &str) -> anyhow::Result<ImageUser> {
+    let request = tonic::Request::new(cri::ImageStatusRequest {
+        image: Some(cri::ImageSpec {
+            image: image.to_string(),
+        }),
+        verbose: false,
+    });
+    debug!("Sending request: {:?}", &request);
+    let response = image_client.image_status(request).await?;
+    Ok(ImageUser::of(response.image.as_ref()))
+}
+
+/// Returns the restart count of each container replacing those of a sandbox about to be removed.
+async fn previous_restart_counts(
+    pod_state: &PodState,
+    sandbox_id: &str,
+) -> anyhow::Result<HashMap<String, u32>> {
+    pod_state.shared.refresh_containers().await?;
+    let containers = pod_state.shared.containers.read().await;
+    Ok(containers
+        .iter()
+        .filter(|((id, _), _)| id == sandbox_id)
+        .map(|((_, name), container)| (name.clone(), labels::next_restart_count(container)))
+        .collect())
+}
+
+/// Runs the pod sandbox and creates and starts each container in it.
+async fn start(pod_state: &mut PodState, pod: &Pod) -> anyhow::Result<()> {
+    // Resolved before the sandbox is created, so that a missing ConfigMap or Secret, or an
+    // image breaking runAsNonRoot, leaves nothing to clean up.
+    let kube_client = kube::Client::new(pod_state.shared.kubeconfig.clone());
+    let mut container_envs =
+        env::resolve(kube_client, pod.as_kube_pod(), &pod_state.shared.node_name).await?;
+
+    let mut image_client = pod_state.shared.image.image_client().await?;
+    let mut image_users = HashMap::new();
+    for container in pod.containers() {
+        let image: String = container.image()?.unwrap().into();
+        let kube_container = kube_container(pod, container.name())?;
+        let image_user = image_user(&mut image_client, &image).await?;
+        security_context::verify_run_as_non_root(pod.as_kube_pod(), kube_container, &image_user)?;
+        image_users.insert(container.name().to_string(), image_user);
+    }
+
+    pod_state.shared.refresh_pods().await?;
+
+    let existing_sandbox_id = {
+        pod_state
+            .shared
+            .pods
+            .read()
+            .await
+            .get(&pod_state.pod_uid())
+            .map(|sandbox| sandbox.id.clone())
+    };
+
+    // Containers replacing those of an existing sandbox count as restarts.
+    let mut restart_counts = HashMap::new();
+    if let Some(sandbox_id) = existing_sandbox_id {
+        restart_counts = previous_restart_counts(pod_state, &sandbox_id).await?;
+        stop_and_delete_pod_sandbox(pod_state, pod.clone()).await?;
+    }
+
+    debug!("Starting pod sandbox {}", pod.name());
+    let request = tonic::Request::new(cri::RunPodSandboxRequest {
+        config: Some(pod_state.sandbox_config.clone()),
+        runtime_handler: pod_state.runtime_class.handler.clone(),
+    });
+    debug!("Sending request: {:?}", &request);
+    let mut client = match pod_state.shared.client().await {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Error creating client: {:?}", &e);
+            anyhow::bail!(e);
+        }
+    };
+    let response = match client.run_pod_sandbox(request).await {
+        Ok(response) => response,
+        // A sandbox with the same name was created since the pods were listed.
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            warn!("{}. Removing existing sandbox and retrying.", e);
+            pod_state.shared.refresh_pods().await?;
+            stop_and_delete_pod_sandbox(pod_state, pod.clone()).await?;
+            let request = tonic::Request::new(cri::RunPodSandboxRequest {
+                config: Some(pod_state.sandbox_config.clone()),
+                runtime_handler: pod_state.runtime_class.handler.clone(),
+            });
+            client.run_pod_sandbox(request).await?
+        }
+        // Retryable errors are retried by the caller once the runtime has had time to recover.
+        Err(e) => anyhow::bail!(e),
+    };
+    info!("Started pod sandbox {}: {:?}", pod.name(), &response);
+    let pod_sandbox_id = response.pod_sandbox_id;
+    pod_state.sandbox_id = Some(pod_sandbox_id.clone());
+    pod_state.refresh_ips(&mut client).await?;
+    let hosts_path = hosts::hosts_file_path(&pod_state.shared.pods_dir, &pod_state.pod_uid());
+    hosts::write_hosts_file(
+        &hosts_path,
+        &hosts::hosts_file_content(
+            pod.as_kube_pod(),
+            &pod_state.pod_ips,
+            &pod_state.shared.config.cluster_domain,
+        ),
+    )
+    .await?;
+    let memory_capacity = node_capacity::memory_capacity().await;
+    let allocatable = pod_state.shared.allocatable.read().await.clone();
+    let downward_context = downward::Context {
+        pod: pod.as_kube_pod(),
+        node_name: &pod_state.shared.node_name,
+        pod_ips: &pod_state.pod_ips,
+        host_ip: &pod_state.shared.host_ip,
+        allocatable: &allocatable,
+    };
+    let config = &pod_state.shared.config;
+    let cfs_period = if config.cpu_cfs_quota {
+        Some(config.cpu_cfs_quota_period)
+    } else {
+        None
+    };
+
+    for container in pod.containers() {
+        let image: String = container.image()?.unwrap().into();
+        debug!("Creating container: {}", container.name());
+
+        tokio::fs::create_dir_all(format!(
+            "{}{}",
+            &pod_state.sandbox_config.log_directory,
+            container.name()
+        ))
+        .await?;
+
+        let kube_container = kube_container(pod, container.name())?;
+
+        let restart_count = restart_counts.get(container.name()).cloned().unwrap_or(0);
+        let metadata = Some(cri::ContainerMetadata {
+            name: container.name().to_string(),
+            attempt: restart_count,
+        });
+
+        let image = Some(cri::ImageSpec {
+            image: image.clone(),
+        });
+
+        let envs = env::finish(
+            container_envs.remove(container.name()).unwrap_or_default(),
+            container.name(),
+            &downward_context,
+        )?;
+
+        // Command and args may refer to any of the container's variables.
+        let vars: HashMap<&str, &str> = envs
+            .iter()
+            .map(|var| (var.key.as_str(), var.value.as_str()))
+            .collect();
+        let expand = |input: &String| expansion::expand(input, |name| vars.get(name).copied());
+
+        let command = container.command().iter().flatten().map(expand).collect();
+
+        let args = container.args().iter().flatten().map(expand).collect();
+
+        let working_dir = container
+            .working_dir()
+            .cloned()
+            .unwrap_or_else(|| "/".to_string());
+
+        // TODO: Support volumes
+        let mounts = hosts::hosts_mount(
+            pod.as_kube_pod(),
+            &pod_state.pod_ips,
+            kube_container,
+            &hosts_path,
+        )
+        .into_iter()
+        .collect();
+
+        // TODO
+        let devices = vec![];
+
+        let labels = labels::container_labels(pod.as_kube_pod(), container.name());
+
+        let annotations =
+            labels::container_annotations(pod.as_kube_pod(), kube_container, restart_count);
+
+        let log_path = format!("{}/log", container.name());
+
+        // Containers join the namespaces chosen for the sandbox.
+        let namespace_options = pod_state
+            .sandbox_config
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.security_context.as_ref())
+            .and_then(|security_context| security_context.namespace_options.clone());
+        let linux = Some(cri::LinuxContainerConfig {
+            resources: Some(resources::container_resources(
+                kube_container,
+                pod_state.qos_class,
+                memory_capacity,
+                &allocatable,
+                cfs_period,
+            )?),
+            security_context: Some(security_context::container_security_context(
+                pod.as_kube_pod(),
+                kube_container,
+                namespace_options,
+                &image_users[container.name()],
+                &pod_state.shared.seccomp_root,
+            )),
+        });
+
+        let config = Some(cri::ContainerConfig {
+            metadata,
+            image,
+            command,
+            args,
+            working_dir,
+            envs,
+            mounts,
+            devices,
+            labels,
+            annotations,
+            log_path,
+            stdin: false,
+            stdin_once: false,
+            tty: false,
+            linux,
+            windows: None,
+        });
+
+        let request = tonic::Request::new(cri::CreateContainerRequest {
+            pod_sandbox_id: pod_sandbox_id.clone(),
+            config,
+            sandbox_config: Some(pod_state.sandbox_config.clone()),
+        });
+        debug!("Sending request: {:?}", &request);
+        let response = client.create_container(request).await?;
+        debug!("Created container {}: {:?}", container.name(), &response);
+        let container_id = response.container_id;
+
+        debug!("Starting container: {}", container.name());
+        let request = tonic::Request::new(cri::StartContainerRequest { container_id });
+        debug!("Sending request: {:?}", &request);
+        let response = client.start_container(request).await?;
+        info!("Started container {}: {:?}", container.name(), &response);
+    }
+    Ok(())
+}
+
+fn kube_container<'a>(pod: &'a Pod, name: &str) -> anyhow::Result<&'a Container> {
+    pod.as_kube_pod()
+        .spec
+        .as_ref()
+        .and_then(|spec| {
+            spec.containers
+                .iter()
+                .find(|container| container.name == name)
+        })
+        .ok_or_else(|| anyhow::anyhow!("Container {} not in pod spec.", name))
+}
+
 #[async_trait]
 impl State<PodState> for Starting {
     async fn next(
@@ -17,174 +287,34 @@ impl State<PodState> for Starting {
         pod_state: &mut PodState,
         pod: &Pod,
     ) -> anyhow::Result<Transition<PodState>> {
-        pod_state.shared.refresh_pods().await?;
-
-        let pod_exists = {
-            pod_state
-                .shared
-                .pods
-                .read()
-                .await
-                .contains_key(&(pod.namespace().to_string(), pod.name().to_string()))
-        };
-
-        if pod_exists {
-            stop_and_delete_pod_sandbox(&pod_state, pod.clone()).await?;
-        }
-
-        debug!("Starting pod sandbox {}", pod.name());
-        let request = tonic::Request::new(cri::RunPodSandboxRequest {
-            config: Some(pod_state.sandbox_config.clone()),
-            runtime_handler: "".to_string(),
-        });
-        debug!("Sending request: {:?}", &request);
-        let mut client = match pod_state.shared.client().await {
-            Ok(client) => client,
-            Err(e) => {
-                error!("Error creating client: {:?}", &e);
-                anyhow::bail!(e);
+        match start(pod_state, pod).await {
+            Ok(()) => Ok(Transition::next(self, Running)),
+            Err(e) if e.downcast_ref::<ContainerConfigError>().is_some() => {
+                let message = e.to_string();
+                warn!("Error configuring pod {}: {}", pod.name(), &message);
+                Ok(Transition::next(
+                    self,
+                    CreateContainerConfigError { message },
+                ))
             }
-        };
-        let response = match client.run_pod_sandbox(request).await {
-            Ok(response) => response.into_inner(),
-            Err(e) => {
-                warn!(
-                    "Error creating sandbox: {:?}. Remove existing sandbox and retry.",
-                    e
-                );
-                stop_and_delete_pod_sandbox(&pod_state, pod.clone()).await?;
-                let request = tonic::Request::new(cri::RunPodSandboxRequest {
-                    config: Some(pod_state.sandbox_config.clone()),
-                    runtime_handler: "".to_string(),
-                });
-                match client.run_pod_sandbox(request).await {
-                    Ok(response) => response.into_inner(),
-                    Err(e) => {
-                        error!("Error making request: {:?}", &e);
-                        anyhow::bail!(e);
-                    }
-                }
+            Err(e) if is_retryable(&e) => {
+                warn!("Failed starting pod {}, retrying: {:?}", pod.name(), &e);
+                tokio::time::delay_for(RETRY_DELAY).await;
+                Ok(Transition::next(self, Starting))
             }
-        };
-        info!("Started pod sandbox {}: {:?}", pod.name(), &response);
-        let pod_sandbox_id = response.pod_sandbox_id;
-
-        for container in pod.containers() {
-            let image: String = container.image()?.unwrap().into();
-            debug!("Creating container: {}", container.name());
-
-            tokio::fs::create_dir_all(format!(
-                "/var/log/pods/{}/{}/{}",
-                pod.namespace(),
-                pod.name(),
-                container.name()
-            ))
-            .await?;
-
-            let metadata = Some(cri::ContainerMetadata {
-                name: container.name().to_string(),
-                attempt: 0,
-            });
-
-            let image = Some(cri::ImageSpec {
-                image: image.clone(),
-            });
-
-            let command = container.command().clone().unwrap_or_else(Vec::new);
-
-            let args = container.args().clone().unwrap_or_else(Vec::new);
-
-            let working_dir = container
-                .working_dir()
-                .cloned()
-                .unwrap_or_else(|| "/".to_string());
-
-            // TODO: Support value_from
-            let envs = container
-                .env()
-                .clone()
-                .unwrap_or_else(Vec::new)
-                .into_iter()
-                .filter_map(|env| match env.value {
-                    Some(value) => Some(k8s_cri::v1alpha2::KeyValue {
-                        key: env.name,
-                        value,
-                    }),
-                    None => None,
-                })
-                .collect();
-
-            // TODO
-            let mounts = vec![];
-
-            // TODO
-            let devices = vec![];
-
-            let labels = std::collections::BTreeMap::new();
-
-            let annotations = std::collections::BTreeMap::new();
-
-            let log_path = format!("{}/log", container.name());
-
-            let linux = None;
-
-            let config = Some(cri::ContainerConfig {
-                metadata,
-                image,
-                command,
-                args,
-                working_dir,
-                envs,
-                mounts,
-                devices,
-                labels,
-                annotations,
-                log_path,
-                stdin: false,
-                stdin_once: false,
-                tty: false,
-                linux,
-                windows: None,
-            });
-
-            let request = tonic::Request::new(cri::CreateContainerRequest {
-                pod_sandbox_id: pod_sandbox_id.clone(),
-                config,
-                sandbox_config: Some(pod_state.sandbox_config.clone()),
-            });
-            debug!("Sending request: {:?}", &request);
-            let response = match client.create_container(request).await {
-                Ok(response) => response.into_inner(),
-                Err(e) => {
-                    error!("Error making request: {:?}", &e);
-                    anyhow::bail!(e);
-                }
-            };
-            debug!("Created container {}: {:?}", container.name(), &response);
-            let container_id = response.container_id;
-
-            debug!("Starting container: {}", container.name());
-            let request = tonic::Request::new(cri::StartContainerRequest { container_id });
-            debug!("Sending request: {:?}", &request);
-            let response = match client.start_container(request).await {
-                Ok(response) => response.into_inner(),
-                Err(e) => {
-                    error!("Error making request: {:?}", &e);
-                    anyhow::bail!(e);
-                }
-            };
-            info!("Started container {}: {:?}", container.name(), &response);
+            Err(e) => Err(e),
         }
-        Ok(Transition::next(self, Running))
     }
 
     async fn json_status(
         &self,
-        _pod_state: &mut PodState,
+        pod_state: &mut PodState,
         _pod: &Pod,
     ) -> anyhow::Result<serde_json::Value> {
-        make_status(Phase::Pending, "Starting")
+        make_pod_status(pod_state, Phase::Pending, "Starting")
     }
 }
 
+impl TransitionTo<CreateContainerConfigError> for Starting {}
 impl TransitionTo<Running> for Starting {}
+impl TransitionTo<Starting> for Starting {}
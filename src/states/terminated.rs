@@ -1,4 +1,5 @@
-use super::PodState;
+use super::{make_pod_status, PodState};
+use crate::runtime::ErrorKind;
 use async_trait::async_trait;
 use k8s_cri::v1alpha2 as cri;
 use kubelet::state::prelude::*;
@@ -12,13 +13,7 @@ pub async fn stop_and_delete_pod_sandbox(
     pod_state: &PodState,
     pod: kubelet::pod::Pod,
 ) -> anyhow::Result<()> {
-    match pod_state
-        .shared
-        .pods
-        .read()
-        .await
-        .get(&(pod.namespace().to_string(), pod.name().to_string()))
-    {
+    match pod_state.shared.pods.read().await.get(&pod_state.pod_uid()) {
         Some(pod_sandbox) => {
             debug!("Stopping pod sandbox {}", pod.name());
             let mut client = match pod_state.shared.client().await {
@@ -32,28 +27,28 @@ pub async fn stop_and_delete_pod_sandbox(
                 pod_sandbox_id: pod_sandbox.id.clone(),
             });
             debug!("Sending request: {:?}", &request);
-            let response = match client.stop_pod_sandbox(request).await {
-                Ok(response) => response,
-                Err(e) => {
-                    error!("Error making request: {:?}", &e);
-                    anyhow::bail!(e);
+            match client.stop_pod_sandbox(request).await {
+                Ok(response) => info!("Stopped pod sandbox {}: {:?}", pod.name(), response),
+                // The sandbox is already gone, which is what we wanted.
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    info!("Pod sandbox {} already removed.", pod.name());
+                    return Ok(());
                 }
-            };
-            info!("Stopped pod sandbox {}: {:?}", pod.name(), response);
+                Err(e) => anyhow::bail!(e),
+            }
 
             debug!("Removing pod sandbox {}", pod.name());
             let request = tonic::Request::new(cri::RemovePodSandboxRequest {
                 pod_sandbox_id: pod_sandbox.id.clone(),
             });
             debug!("Sending request: {:?}", &request);
-            let response = match client.remove_pod_sandbox(request).await {
-                Ok(response) => response,
-                Err(e) => {
-                    error!("Error making request: {:?}", &e);
-                    anyhow::bail!(e);
+            match client.remove_pod_sandbox(request).await {
+                Ok(response) => info!("Removed pod sandbox {}: {:?}", pod.name(), response),
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    info!("Pod sandbox {} already removed.", pod.name())
                 }
-            };
-            info!("Removed pod sandbox {}: {:?}", pod.name(), response);
+                Err(e) => anyhow::bail!(e),
+            }
             Ok(())
         }
         None => {
@@ -71,7 +66,7 @@ impl State<PodState> for Terminated {
         pod: &Pod,
     ) -> anyhow::Result<Transition<PodState>> {
         pod_state.shared.refresh_pods().await?;
-        stop_and_delete_pod_sandbox(&pod_state, pod.clone()).await?;
+        stop_and_delete_pod_sandbox(pod_state, pod.clone()).await?;
         let dp = kube::api::DeleteParams {
             grace_period_seconds: Some(0),
             ..Default::default()
@@ -87,9 +82,9 @@ impl State<PodState> for Terminated {
 
     async fn json_status(
         &self,
-        _pod_state: &mut PodState,
+        pod_state: &mut PodState,
         _pod: &Pod,
     ) -> anyhow::Result<serde_json::Value> {
-        make_status(Phase::Succeeded, "Terminated")
+        make_pod_status(pod_state, Phase::Succeeded, "Terminated")
     }
 }
@@ -0,0 +1,128 @@
+//! Fixtures shared by unit tests.
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response};
+use k8s_openapi::api::core::v1::Pod as KubePod;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::{CgroupDriver, Config, CriEndpoint, Timeouts};
+
+/// KrustletCRI configuration with the default endpoints and timeouts, and no reservations or
+/// eviction thresholds.
+pub fn config() -> Config {
+    let endpoint = CriEndpoint::Unix("/run/containerd/containerd.sock".into());
+    Config {
+        runtime_endpoint: endpoint.clone(),
+        image_endpoint: endpoint,
+        kube_reserved: Default::default(),
+        system_reserved: Default::default(),
+        eviction_hard: Default::default(),
+        timeouts: Timeouts {
+            runtime: Duration::from_secs(2 * 60),
+            image_pull: Duration::from_secs(10 * 60),
+            status: Duration::from_secs(10),
+        },
+        cluster_dns: vec![],
+        cluster_domain: "cluster.local".to_string(),
+        resolv_conf: String::new(),
+        allowed_unsafe_sysctls: vec![],
+        cgroup_driver: CgroupDriver::Cgroupfs,
+        cpu_cfs_quota: true,
+        cpu_cfs_quota_period: Duration::from_millis(100),
+        seccomp_profile_root: None,
+    }
+}
+
+/// Builds pod `ns/web-0` with UID `1234` from its spec, which runs a single `app` container
+/// unless it lists its own.
+pub fn kube_pod(mut spec: serde_json::Value) -> KubePod {
+    if spec.get("containers").is_none() {
+        spec["containers"] = serde_json::json!([{ "name": "app" }]);
+    }
+    serde_json::from_value(serde_json::json!({
+        "metadata": { "name": "web-0", "namespace": "ns", "uid": "1234" },
+        "spec": spec,
+    }))
+    .unwrap()
+}
+
+/// Like [`kube_pod`], wrapped as a Kubelet pod.
+pub fn pod(spec: serde_json::Value) -> kubelet::pod::Pod {
+    kubelet::pod::Pod::new(kube_pod(spec))
+}
+
+/// A request received by a test API server: its method, path and body.
+pub type Request = (hyper::Method, String, serde_json::Value);
+
+/// Starts an API server which answers requests for each path with a status and JSON body, and
+/// returns a client for it. Other paths are not found.
+pub fn api_server(routes: Vec<(&'static str, u16, serde_json::Value)>) -> kube::Client {
+    kube::Client::new(api_config(routes))
+}
+
+/// Like [`api_server`], returning the configuration to reach it.
+pub fn api_config(routes: Vec<(&'static str, u16, serde_json::Value)>) -> kube::Config {
+    recording_api_config(routes).0
+}
+
+/// Like [`api_server`], also returning the requests it has received so far.
+pub fn recording_api_server(
+    routes: Vec<(&'static str, u16, serde_json::Value)>,
+) -> (kube::Client, Arc<Mutex<Vec<Request>>>) {
+    let (config, requests) = recording_api_config(routes);
+    (kube::Client::new(config), requests)
+}
+
+fn recording_api_config(
+    routes: Vec<(&'static str, u16, serde_json::Value)>,
+) -> (kube::Config, Arc<Mutex<Vec<Request>>>) {
+    let routes = Arc::new(routes);
+    let requests: Arc<Mutex<Vec<Request>>> = Default::default();
+    let received = requests.clone();
+    let make_service = make_service_fn(move |_| {
+        let routes = routes.clone();
+        let received = received.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: hyper::Request<Body>| {
+                let routes = routes.clone();
+                let received = received.clone();
+                async move {
+                    let (parts, body) = request.into_parts();
+                    let path = parts.uri.path().to_string();
+                    let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+                    received.lock().unwrap().push((
+                        parts.method,
+                        path.clone(),
+                        serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null),
+                    ));
+                    let route = routes.iter().find(|(route, _, _)| *route == path);
+                    let (status, body) = match route {
+                        Some((_, status, body)) => (*status, body.clone()),
+                        None => (
+                            404,
+                            serde_json::json!({
+                                "kind": "Status",
+                                "apiVersion": "v1",
+                                "status": "Failure",
+                                "message": format!("{} not found", path),
+                                "reason": "NotFound",
+                                "code": 404,
+                            }),
+                        ),
+                    };
+                    let response = Response::builder()
+                        .status(status)
+                        .header("content-type", "application/json")
+                        .body(Body::from(body.to_string()));
+                    Ok::<_, Infallible>(response.unwrap())
+                }
+            }))
+        }
+    });
+    let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let url = format!("http://{}", server.local_addr()).parse().unwrap();
+    tokio::spawn(server);
+    (kube::Config::new(url), requests)
+}